@@ -3,9 +3,67 @@
 // Copyright (c) 2025 Ferrous Systems
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use std::env;
+
+use arm_targets::{Arch, Profile};
+
 /// Entry point to the build script
 fn main() {
-    arm_targets::process();
+    let target_info = arm_targets::process();
+
+    // Pick the port, so the code can say `#[cfg(pets_port = "...")]`
+    let arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap_or_default();
+    let os = env::var("CARGO_CFG_TARGET_OS").unwrap_or_default();
+    let port = match (arch.as_str(), os.as_str()) {
+        ("arm", _) if target_info.profile() == Some(Profile::M) => "cortex-m",
+        ("arm", _) if target_info.profile() == Some(Profile::R) => "cortex-r",
+        ("riscv32", "none") if riscv_has_fpu() => panic!(
+            "pets does not support RISC-V targets with floating-point registers, \
+            like {:?}, because it doesn't save them when it switches tasks",
+            env::var("TARGET").unwrap_or_default()
+        ),
+        ("riscv32", "none") => "riscv",
+        (_, "none") => panic!("pets does not support {:?}", env::var("TARGET")),
+        _ => "sim",
+    };
+    println!(
+        r#"cargo::rustc-check-cfg=cfg(pets_port, values("cortex-m", "cortex-r", "riscv", "sim"))"#
+    );
+    println!(r#"cargo::rustc-cfg=pets_port="{}""#, port);
+
+    // Cortex-M CPUs with BASEPRI can have critical sections which leave the
+    // most urgent interrupts alone
+    println!("cargo::rustc-check-cfg=cfg(pets_basepri)");
+    if matches!(
+        target_info.arch(),
+        Some(Arch::Armv7M | Arch::Armv7EM | Arch::Armv8MMain)
+    ) {
+        println!("cargo::rustc-cfg=pets_basepri");
+    }
+}
+
+/// Does the RISC-V CPU we are building for have floating-point registers?
+///
+/// Stable Rust doesn't set `target_feature = "f"` (or `"d"`), so we also look
+/// at the extensions in the target name (e.g. `riscv32imafc`, where `g`
+/// means `imafd`).
+fn riscv_has_fpu() -> bool {
+    let features = env::var("CARGO_CFG_TARGET_FEATURE").unwrap_or_default();
+    if features
+        .split(',')
+        .any(|feature| feature == "f" || feature == "d")
+    {
+        return true;
+    }
+    let target = env::var("TARGET").unwrap_or_default();
+    let extensions = target
+        .split('-')
+        .next()
+        .and_then(|arch| arch.strip_prefix("riscv32"))
+        .unwrap_or_default();
+    // Multi-letter extensions come after an underscore
+    let single_letter = extensions.split('_').next().unwrap_or_default();
+    single_letter.contains(['f', 'd', 'g'])
 }
 
 // End of File
@@ -0,0 +1,14 @@
+/* Memory layout for QEMU's mps3-an536 machine */
+
+/* Copyright (c) 2025 Ferrous Systems */
+/* SPDX-License-Identifier: CC0-1.0 */
+
+MEMORY
+{
+  QSPI : ORIGIN = 0x08000000, LENGTH = 8M
+  BRAM : ORIGIN = 0x10000000, LENGTH = 512K
+}
+
+REGION_ALIAS("VECTORS", QSPI);
+REGION_ALIAS("CODE", QSPI);
+REGION_ALIAS("DATA", BRAM);
@@ -0,0 +1,125 @@
+//! A simple test program we can run in CI, on QEMU's mps3-an536 machine
+//!
+//! It starts three tasks, each of which periodically prints a defmt log and
+//! then sleeps. After 5 loops of the slowest task, it exits.
+//!
+//! This does the same as `ci-check` in the Arm examples, so the
+//! `pets-qemu-test` tool can check it in the same way.
+
+// Copyright (c) 2025 Ferrous Systems
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+#![no_std]
+#![no_main]
+
+use core::ptr::NonNull;
+
+use arm_gic::{
+    IntId, InterruptGroup, UniqueMmioPointer,
+    gicv3::{GicCpuInterface, GicV3},
+};
+use pets::{Scheduler, Stack, Task};
+
+use pets_examples_cortex_r as _;
+
+/// The base address of the GIC Distributor on the mps3-an536 machine
+const GICD_BASE: usize = 0xF000_0000;
+
+/// The base address of the GIC Redistributors on the mps3-an536 machine
+const GICR_BASE: usize = 0xF010_0000;
+
+/// The Generic Timer's virtual timer interrupt
+const VIRTUAL_TIMER_PPI: IntId = IntId::ppi(11);
+
+/// How many scheduler ticks we want per second
+const SCHED_TICKS_PER_SECOND: u32 = 100;
+
+static TASK_LIST: [Task; 3] = [
+    Task::new(rabbits, &RABBIT_STACK).with_name("rabbit"),
+    Task::new(hamsters, &HAMSTER_STACK).with_name("hamster"),
+    Task::new(cats, &CAT_STACK).with_name("cat"),
+];
+
+static SCHEDULER: Scheduler = Scheduler::new(&TASK_LIST);
+
+#[cortex_r_rt::entry]
+fn main() -> ! {
+    defmt::info!("Hello!");
+
+    // SAFETY: This is where the GIC is on the mps3-an536 machine, and
+    // nothing else is using it
+    let mut gic = unsafe {
+        GicV3::new(
+            UniqueMmioPointer::new(NonNull::new(GICD_BASE as *mut _).unwrap()),
+            NonNull::new(GICR_BASE as *mut _).unwrap(),
+            1,
+        )
+    }
+    .unwrap();
+    gic.setup(0);
+    GicCpuInterface::set_priority_mask(0xFF);
+    gic.set_interrupt_priority(VIRTUAL_TIMER_PPI, Some(0), 0x80)
+        .unwrap();
+    gic.enable_interrupt(VIRTUAL_TIMER_PPI, Some(0), true)
+        .unwrap();
+
+    SCHEDULER.start(timer_frequency() / SCHED_TICKS_PER_SECOND);
+}
+
+/// Read the Generic Timer frequency, from CNTFRQ
+fn timer_frequency() -> u32 {
+    let frequency: u32;
+    // SAFETY: Reading CNTFRQ has no side effects
+    unsafe {
+        core::arch::asm!("mrc p15, 0, {0}, c14, c0, 0", out(reg) frequency);
+    }
+    frequency
+}
+
+/// Called by pets when an interrupt fires
+#[unsafe(no_mangle)]
+extern "C" fn _irq_handler() {
+    while let Some(intid) = GicCpuInterface::get_and_acknowledge_interrupt(InterruptGroup::Group1) {
+        if intid == VIRTUAL_TIMER_PPI {
+            pets::cortex_r::on_timer_interrupt();
+        } else {
+            defmt::warn!("Unexpected interrupt {}", defmt::Debug2Format(&intid));
+        }
+        GicCpuInterface::end_interrupt(intid, InterruptGroup::Group1);
+    }
+}
+
+static RABBIT_STACK: Stack<1024> = Stack::new();
+
+/// Our 'rabbit' task
+fn rabbits() -> ! {
+    let mut counter = 0;
+    loop {
+        defmt::info!("Rabbit! (back in 5) count={=u32}", counter);
+        counter += 1;
+        pets::delay(5);
+    }
+}
+
+static HAMSTER_STACK: Stack<1024> = Stack::new();
+
+/// Our 'hamster' task
+fn hamsters() -> ! {
+    for i in 0..5 {
+        defmt::info!("Hamster {}! (back in 10)", i);
+        pets::delay(10);
+    }
+    semihosting::process::exit(0);
+}
+
+static CAT_STACK: Stack<1024> = Stack::new();
+
+/// Our 'cat' task
+fn cats() -> ! {
+    loop {
+        defmt::info!("Cat! (back in 3)");
+        pets::delay(3);
+    }
+}
+
+// End of File
@@ -0,0 +1,16 @@
+/* Memory layout for QEMU's virt machine, running from RAM */
+
+/* Copyright (c) 2025 Ferrous Systems */
+/* SPDX-License-Identifier: CC0-1.0 */
+
+MEMORY
+{
+  RAM : ORIGIN = 0x80000000, LENGTH = 16M
+}
+
+REGION_ALIAS("REGION_TEXT", RAM);
+REGION_ALIAS("REGION_RODATA", RAM);
+REGION_ALIAS("REGION_DATA", RAM);
+REGION_ALIAS("REGION_BSS", RAM);
+REGION_ALIAS("REGION_HEAP", RAM);
+REGION_ALIAS("REGION_STACK", RAM);
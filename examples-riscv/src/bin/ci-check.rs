@@ -0,0 +1,72 @@
+//! A simple test program we can run in CI, on QEMU's RISC-V virt machine
+//!
+//! It starts three tasks, each of which periodically prints a defmt log and
+//! then sleeps. After 5 loops of the slowest task, it exits.
+//!
+//! This does the same as `ci-check` in the Arm examples, so the
+//! `pets-qemu-test` tool can check it in the same way.
+
+// Copyright (c) 2025 Ferrous Systems
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+#![no_std]
+#![no_main]
+
+use pets::{Scheduler, Stack, Task, riscv::Clint};
+
+use pets_examples_riscv as _;
+
+/// The virt machine's `mtime` runs at 10 MHz, so this is a 10ms tick
+const MTIME_PER_SCHED_TICK: u32 = 100_000;
+
+static TASK_LIST: [Task; 3] = [
+    Task::new(rabbits, &RABBIT_STACK).with_name("rabbit"),
+    Task::new(hamsters, &HAMSTER_STACK).with_name("hamster"),
+    Task::new(cats, &CAT_STACK).with_name("cat"),
+];
+
+static SCHEDULER: Scheduler = Scheduler::new(&TASK_LIST);
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    defmt::info!("Hello!");
+    // SAFETY: This is where the CLINT is on the virt machine, and nothing
+    // else is using it
+    let clint = unsafe { Clint::new(Clint::QEMU_BASE) };
+    SCHEDULER.start(clint, MTIME_PER_SCHED_TICK);
+}
+
+static RABBIT_STACK: Stack<1024> = Stack::new();
+
+/// Our 'rabbit' task
+fn rabbits() -> ! {
+    let mut counter = 0;
+    loop {
+        defmt::info!("Rabbit! (back in 5) count={=u32}", counter);
+        counter += 1;
+        pets::delay(5);
+    }
+}
+
+static HAMSTER_STACK: Stack<1024> = Stack::new();
+
+/// Our 'hamster' task
+fn hamsters() -> ! {
+    for i in 0..5 {
+        defmt::info!("Hamster {}! (back in 10)", i);
+        pets::delay(10);
+    }
+    semihosting::process::exit(0);
+}
+
+static CAT_STACK: Stack<1024> = Stack::new();
+
+/// Our 'cat' task
+fn cats() -> ! {
+    loop {
+        defmt::info!("Cat! (back in 3)");
+        pets::delay(3);
+    }
+}
+
+// End of File
@@ -0,0 +1,24 @@
+//! Common panic/timestamp handlers for the RISC-V examples
+
+#![no_std]
+
+use defmt_semihosting as _;
+
+/// Called when a panic occurs.
+///
+/// Logs the panic to defmt and then tells QEMU to exit.
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    defmt::println!("PANIC: {}", defmt::Debug2Format(info));
+    semihosting::process::exit(1);
+}
+
+// Log scheduler ticks, and the time in timer counts, in the defmt logs
+defmt::timestamp!(
+    "{=u32:010} {=u64:012} {}",
+    pets::now(),
+    pets::now_precise(),
+    pets::task_id()
+);
+
+// End of File
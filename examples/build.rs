@@ -9,14 +9,41 @@ use std::{env, error::Error, fs, path::PathBuf};
 
 use arm_targets::Arch;
 
+/// CPUs with the M-profile Vector Extension (Helium)
+///
+/// Rust has no Armv8.1-M target yet, so you build for
+/// `thumbv8m.main-none-eabihf` with `-C target-cpu=...` set to one of these.
+const MVE_CPUS: &[&str] = &["cortex-m55", "cortex-m85"];
+
+/// Find the `-C target-cpu=...` flag, if any
+fn target_cpu() -> Option<String> {
+    let flags = env::var("CARGO_ENCODED_RUSTFLAGS").unwrap_or_default();
+    let flags: Vec<&str> = flags.split('\x1f').collect();
+    flags.iter().enumerate().find_map(|(idx, flag)| {
+        let setting = match *flag {
+            "-C" | "--codegen" => flags.get(idx + 1)?,
+            flag => flag.strip_prefix("-C")?,
+        };
+        setting.strip_prefix("target-cpu=").map(str::to_string)
+    })
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
     let target_info = arm_targets::process();
     let out_dir = PathBuf::from(env::var("OUT_DIR")?);
+    let mve = target_cpu().is_some_and(|cpu| MVE_CPUS.contains(&cpu.as_str()));
+    let trustzone = env::var_os("CARGO_FEATURE_TRUSTZONE").is_some();
     // put memory layout (linker script) in the linker search path as the
     // package root isn't always searched
 
     let memory_file = match target_info.arch() {
+        // Our Secure world is written for the AN505's Cortex-M33
+        Some(Arch::Armv8MMain) if trustzone && !mve => "memory_mps2tz_ns.x",
+        _ if trustzone => {
+            panic!("The trustzone feature needs thumbv8m.main, without Helium");
+        }
         Some(Arch::Armv6M | Arch::Armv7M | Arch::Armv7EM) => "memory_mps2.x",
+        Some(Arch::Armv8MMain) if mve => "memory_mps3.x",
         Some(Arch::Armv8MBase | Arch::Armv8MMain) => "memory_mps2tz.x",
         _ => {
             panic!("Target {:?} not supported", std::env::var("TARGET"));
@@ -27,6 +54,11 @@ fn main() -> Result<(), Box<dyn Error>> {
     println!("cargo::rerun-if-changed={}", memory_file);
     // tell the linker where to find them
     println!("cargo::rustc-link-search={}", out_dir.display());
+    // let the examples say `#[cfg(mve)]`
+    println!("cargo::rustc-check-cfg=cfg(mve)");
+    if mve {
+        println!("cargo::rustc-cfg=mve");
+    }
     Ok(())
 }
 
@@ -0,0 +1,56 @@
+/* Memory Configuration Linker Script
+
+This file is imported by cortex-m-rt's link.x script, and should be placed
+somewhere in the linker's search path.
+
+Copyright (c) 2025 Ferrous Systems
+SPDX-License-Identifier: CC0-1.0
+*/
+
+/*
+Settings for AN505 on MPS2, with the examples in the Non-secure world
+
+The Secure world (see `src/secure.rs`) lives in the first megabyte of SSRAM1,
+using the Secure alias. The example lives in the rest of SSRAM1, using the
+Non-secure alias. SSRAM2 holds the example's data, and SSRAM3 (using the
+Secure alias) holds the Secure world's data.
+*/
+
+MEMORY
+{
+  SECURE_FLASH : ORIGIN = 0x10000000, LENGTH = 1M
+         FLASH : ORIGIN = 0x00100000, LENGTH = 3M
+           RAM : ORIGIN = 0x28000000, LENGTH = 2M
+    SECURE_RAM : ORIGIN = 0x38200000, LENGTH = 2M
+}
+
+SECTIONS
+{
+  /* The CPU boots from the start of the Secure alias of SSRAM1 */
+  .secure_vector_table ORIGIN(SECURE_FLASH) :
+  {
+    KEEP(*(.secure_vector_table));
+  } > SECURE_FLASH
+
+  .secure_text :
+  {
+    *(.secure_text);
+  } > SECURE_FLASH
+
+  /* The SAU works in 32 byte chunks, and only these can be Non-secure Callable */
+  .secure_gateways : ALIGN(32)
+  {
+    __secure_gateways_start = .;
+    KEEP(*(.secure_gateways));
+    . = ALIGN(32);
+    __secure_gateways_end = .;
+  } > SECURE_FLASH
+
+  .secure_bss (NOLOAD) : ALIGN(8)
+  {
+    *(.secure_bss);
+  } > SECURE_RAM
+}
+INSERT BEFORE .vector_table;
+
+__secure_stack_top = ORIGIN(SECURE_RAM) + LENGTH(SECURE_RAM);
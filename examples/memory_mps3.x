@@ -0,0 +1,19 @@
+/* Memory Configuration Linker Script
+
+This file is imported by cortex-m-rt's link.x script, and should be placed
+somewhere in the linker's search path.
+
+Copyright (c) 2025 Ferrous Systems
+SPDX-License-Identifier: CC0-1.0
+*/
+
+/*
+Settings for AN547 on MPS3 (Cortex-M55), using the Secure aliases of the
+ITCM and DTCM
+*/
+
+MEMORY
+{
+  FLASH : ORIGIN = 0x10000000, LENGTH = 512K
+    RAM : ORIGIN = 0x30000000, LENGTH = 512K
+}
@@ -2,6 +2,19 @@
 //!
 //! It starts three tasks, each of which periodically prints a defmt log and
 //! then sleeps. After 5 loops of the slowest task, it exits.
+//!
+//! The `pets-qemu-test` tool runs this in QEMU and checks the log. If you
+//! change what the tasks do, update the tool to match.
+//!
+//! When built for a CPU with Helium (e.g. with `-C target-cpu=cortex-m55`),
+//! it also starts two tasks which check the vector registers and VPR survive
+//! being switched out.
+//!
+//! When built with the `trustzone` feature, the tasks run in the Non-secure
+//! world, and it also starts two tasks which get pre-empted whilst they are
+//! in the Secure world, to check each one keeps its own Secure stack. A third
+//! task is woken by an interrupt which fires part way through switching to
+//! one of those two, so the scheduler changes its mind mid-switch.
 
 // Copyright (c) 2025 Ferrous Systems
 // SPDX-License-Identifier: GPL-3.0-or-later
@@ -15,10 +28,30 @@ use pets_examples as _;
 
 const SYSTICKS_PER_SCHED_TICK: u32 = 100_000;
 
-static TASK_LIST: [Task; 3] = [
-    Task::new(rabbits, &RABBIT_STACK),
-    Task::new(hamsters, &HAMSTER_STACK),
-    Task::new(cats, &CAT_STACK),
+/// How many tasks we have - the Helium checks add two more, and the
+/// TrustZone checks add three
+const TASK_COUNT: usize = if cfg!(feature = "trustzone") {
+    6
+} else if cfg!(mve) {
+    5
+} else {
+    3
+};
+
+static TASK_LIST: [Task; TASK_COUNT] = [
+    Task::new(rabbits, &RABBIT_STACK).with_name("rabbit"),
+    Task::new(hamsters, &HAMSTER_STACK).with_name("hamster"),
+    Task::new(cats, &CAT_STACK).with_name("cat"),
+    #[cfg(mve)]
+    Task::new(helium::mice, &helium::MOUSE_STACK).with_name("mouse"),
+    #[cfg(mve)]
+    Task::new(helium::beavers, &helium::BEAVER_STACK).with_name("beaver"),
+    #[cfg(feature = "trustzone")]
+    Task::new(secure::hedgehogs, &secure::HEDGEHOG_STACK).with_name("hedgehog"),
+    #[cfg(feature = "trustzone")]
+    Task::new(secure::tortoises, &secure::TORTOISE_STACK).with_name("tortoise"),
+    #[cfg(feature = "trustzone")]
+    Task::new(secure::owls, &secure::OWL_STACK).with_name("owl"),
 ];
 
 static SCHEDULER: Scheduler = Scheduler::new(&TASK_LIST);
@@ -27,6 +60,13 @@ static SCHEDULER: Scheduler = Scheduler::new(&TASK_LIST);
 fn main() -> ! {
     let cp = cortex_m::Peripherals::take().unwrap();
     defmt::info!("Hello!");
+    // SAFETY: These are the Non-secure Callable functions from our Secure world
+    #[cfg(feature = "trustzone")]
+    unsafe {
+        pets::trustzone::set_secure_context_api(&pets_examples::SECURE_CONTEXT_API);
+    }
+    #[cfg(feature = "trustzone")]
+    secure::interrupt_switches();
     SCHEDULER.start(cp.SYST, SYSTICKS_PER_SCHED_TICK);
 }
 
@@ -63,4 +103,247 @@ fn cats() -> ! {
     }
 }
 
+/// Tasks which check the Helium state is preserved across task switches
+#[cfg(mve)]
+mod helium {
+    use pets::Stack;
+
+    /// The address of the SysTick Current Value Register
+    const SYST_CVR: *const u32 = 0xE000_E018 as *const u32;
+
+    pub static MOUSE_STACK: Stack<1024> = Stack::new();
+
+    /// Our 'mouse' task
+    ///
+    /// Scribbles over the vector registers and VPR once a tick, so that the
+    /// beaver would notice if its state wasn't restored.
+    pub fn mice() -> ! {
+        loop {
+            // SAFETY: We only write to registers we say we clobber, and to
+            // VPR.P0, which nothing else in this task relies on
+            unsafe {
+                core::arch::asm!(
+                    "vmsr    p0, {garbage}",
+                    "vdup.32 q0, {garbage}",
+                    "vdup.32 q4, {garbage}",
+                    "vdup.32 q7, {garbage}",
+                    garbage = in(reg) 0xDEAD_BEEF_u32,
+                    out("s0") _, out("s1") _, out("s2") _, out("s3") _,
+                    out("s16") _, out("s17") _, out("s18") _, out("s19") _,
+                    out("s28") _, out("s29") _, out("s30") _, out("s31") _,
+                );
+            }
+            pets::delay(1);
+        }
+    }
+
+    pub static BEAVER_STACK: Stack<1024> = Stack::new();
+
+    /// Our 'beaver' task
+    ///
+    /// Loads the vector registers and VPR, spins until the scheduler has
+    /// ticked (and so switched to the other tasks and back), then checks
+    /// they still hold what we loaded. We never sleep, so we use up whatever
+    /// time the other tasks leave.
+    pub fn beavers() -> ! {
+        let mut round: u32 = 0;
+        loop {
+            let value = 0x0BEA_0000 | (round & 0xFFFF);
+            let predicate = round & 0xFFFF;
+            let (q0, q4, q7, p0): (u32, u32, u32, u32);
+            // SAFETY: We only write to registers we say we clobber, and we
+            // put VPR back how we found it
+            unsafe {
+                core::arch::asm!(
+                    "vmrs    {saved_vpr}, vpr",
+                    "vmsr    p0, {p0}",
+                    "vdup.32 q0, {q0}",
+                    "vdup.32 q4, {q0}",
+                    "vdup.32 q7, {q0}",
+                    // Spin until SysTick reloads
+                    "ldr     {prev}, [{cvr}]",
+                    "2:",
+                    "ldr     {now}, [{cvr}]",
+                    "cmp     {now}, {prev}",
+                    "mov     {prev}, {now}",
+                    "bls     2b",
+                    "vmrs    {p0}, p0",
+                    "vmov    {q0}, s0",
+                    "vmov    {q4}, s19",
+                    "vmov    {q7}, s28",
+                    "vmsr    vpr, {saved_vpr}",
+                    cvr = in(reg) SYST_CVR,
+                    saved_vpr = out(reg) _,
+                    prev = out(reg) _,
+                    now = out(reg) _,
+                    p0 = inout(reg) predicate => p0,
+                    q0 = inout(reg) value => q0,
+                    q4 = lateout(reg) q4,
+                    q7 = lateout(reg) q7,
+                    out("s0") _, out("s1") _, out("s2") _, out("s3") _,
+                    out("s16") _, out("s17") _, out("s18") _, out("s19") _,
+                    out("s28") _, out("s29") _, out("s30") _, out("s31") _,
+                );
+            }
+            if (q0, q4, q7, p0) != (value, value, value, predicate) {
+                defmt::error!(
+                    "Helium state lost: Q0={=u32:08x} Q4={=u32:08x} Q7={=u32:08x} P0={=u32:04x}, expected {=u32:08x} / {=u32:04x}",
+                    q0,
+                    q4,
+                    q7,
+                    p0,
+                    value,
+                    predicate
+                );
+                panic!("Helium state lost");
+            }
+            if round.is_multiple_of(10) {
+                defmt::debug!("Helium state preserved for {=u32} rounds", round);
+            }
+            round = round.wrapping_add(1);
+        }
+    }
+}
+
+/// Tasks which check each task keeps its own Secure stack
+///
+/// The tortoise is pre-empted inside the Secure world on every tick. Every
+/// 15 ticks the hedgehog joins it, so that both are in the Secure world at
+/// once and each must get its own Secure stack back when it resumes.
+///
+/// Every few times one of them is switched in, we pend an interrupt which
+/// wakes the owl. It fires after the scheduler has loaded the incoming
+/// task's Secure context, but before PendSV has switched to that task. If
+/// the port switched to the owl there and then, the owl would run on the
+/// other task's Secure context, and the next save would clobber it.
+#[cfg(feature = "trustzone")]
+mod secure {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use cortex_m::{interrupt::InterruptNumber, peripheral::NVIC};
+    use pets::{Deferred, Stack, TaskId, TraceEvent};
+
+    /// How often the hedgehog goes into the Secure world
+    ///
+    /// Whilst it is there, it holds up the other tasks for a tick. That's OK
+    /// on the tick after a multiple of 15, because none of them log then.
+    const HEDGEHOG_PERIOD: u32 = 15;
+
+    /// How much Secure stack each task asks for
+    const SECURE_STACK_SIZE: usize = 1024;
+
+    pub static HEDGEHOG_STACK: Stack<1024> = Stack::new();
+
+    /// Our 'hedgehog' task
+    pub fn hedgehogs() -> ! {
+        pets::trustzone::allocate_secure_context(SECURE_STACK_SIZE).unwrap();
+        let mut round: u32 = 0;
+        loop {
+            pets::delay(HEDGEHOG_PERIOD - (pets::now() % HEDGEHOG_PERIOD));
+            check_secure_stack(0x4ED6_0000 | (round & 0xFFFF));
+            defmt::debug!("Secure stack preserved for {=u32} rounds", round);
+            round = round.wrapping_add(1);
+        }
+    }
+
+    /// The tasks which have a Secure context
+    const SECURE_TASKS: core::ops::Range<usize> = 3..5;
+
+    /// Pend the interrupt on one in this many switches to a Secure task
+    const INTERRUPT_PERIOD: usize = 4;
+
+    /// How many times a task with a Secure context has been switched in
+    static SECURE_SWITCHES: AtomicUsize = AtomicUsize::new(0);
+
+    /// Work for the owl, posted by our interrupt handler
+    static OWL_WORK: Deferred<4> = Deferred::new();
+
+    /// The interrupt the Secure world lets us have
+    #[derive(Copy, Clone)]
+    struct NonSecureIrq;
+
+    // SAFETY: The Secure world made this interrupt Non-secure for us
+    unsafe impl InterruptNumber for NonSecureIrq {
+        fn number(self) -> u16 {
+            pets_examples::NON_SECURE_IRQ
+        }
+    }
+
+    /// Start interrupting switches to the tasks with a Secure context
+    pub fn interrupt_switches() {
+        pets::set_trace_hook(on_trace_event);
+        // SAFETY: Our handler only posts work to the owl, which is safe at
+        // any time
+        unsafe {
+            NVIC::unmask(NonSecureIrq);
+        }
+    }
+
+    /// Our trace hook, which is called from within PendSV as it switches
+    fn on_trace_event(event: TraceEvent, task_id: TaskId, _timestamp: u32) {
+        if event == TraceEvent::SwitchIn && SECURE_TASKS.contains(&task_id.index()) {
+            let switches = SECURE_SWITCHES.fetch_add(1, Ordering::Relaxed);
+            if switches.is_multiple_of(INTERRUPT_PERIOD) {
+                NVIC::pend(NonSecureIrq);
+            }
+        }
+    }
+
+    /// Our interrupt handler, which wakes the owl
+    #[cortex_m_rt::exception]
+    unsafe fn DefaultHandler(irqn: i16) {
+        if irqn != pets_examples::NON_SECURE_IRQ as i16 {
+            panic!("Unexpected interrupt {}", irqn);
+        }
+        OWL_WORK
+            .post(hoot, SECURE_SWITCHES.load(Ordering::Relaxed))
+            .unwrap();
+    }
+
+    pub static OWL_STACK: Stack<1024> = Stack::new();
+
+    /// Our 'owl' task, which only runs when our interrupt wakes it
+    pub fn owls() -> ! {
+        OWL_WORK.run()
+    }
+
+    /// The work our interrupt handler asks the owl to do
+    fn hoot(switches: usize) {
+        defmt::debug!("Owl woken after {=usize} Secure task switches", switches);
+    }
+
+    pub static TORTOISE_STACK: Stack<1024> = Stack::new();
+
+    /// Our 'tortoise' task
+    ///
+    /// We never sleep, so we use up whatever time the other tasks leave -
+    /// and almost all of it is spent in the Secure world.
+    pub fn tortoises() -> ! {
+        pets::trustzone::allocate_secure_context(SECURE_STACK_SIZE).unwrap();
+        let mut round: u32 = 0;
+        loop {
+            check_secure_stack(0x7047_0000 | (round & 0xFFFF));
+            if round.is_multiple_of(10) {
+                defmt::debug!("Secure stack preserved for {=u32} rounds", round);
+            }
+            round = round.wrapping_add(1);
+        }
+    }
+
+    /// Leave a value on our Secure stack until the next tick, and check it
+    /// is still there afterwards
+    fn check_secure_stack(value: u32) {
+        // SAFETY: We allocated a Secure context before calling this
+        let result = unsafe { pets_examples::secure_wait_for_tick(value) };
+        if result != value {
+            defmt::error!(
+                "Secure stack lost: got {=u32:08x}, expected {=u32:08x}",
+                result,
+                value
+            );
+            panic!("Secure stack lost");
+        }
+    }
+}
+
 // End of File
@@ -9,29 +9,27 @@
 #![no_std]
 #![no_main]
 
-use pets::{Scheduler, Stack, Task};
-
 use pets_examples as _;
 
 const SYSTICKS_PER_SCHED_TICK: u32 = 100_000;
 
-static TASK_LIST: [Task; 3] = [
-    Task::new(rabbits, &RABBIT_STACK),
-    Task::new(hamsters, &HAMSTER_STACK),
-    Task::new(cats, &CAT_STACK),
-];
-
-static SCHEDULER: Scheduler = Scheduler::new(&TASK_LIST);
+pets::tasks! {
+    static SCHEDULER = [
+        Task { entry: rabbits, stack: 1024, name: "rabbit" },
+        Task { entry: hamsters, stack: 1024, name: "hamster" },
+        Task { entry: cats, stack: 1024, name: "cat" },
+    ];
+}
 
 #[cortex_m_rt::entry]
 fn main() -> ! {
     let cp = cortex_m::Peripherals::take().unwrap();
     defmt::info!("Hello!");
+    #[cfg(feature = "trace")]
+    pets::set_trace_hook(pets::defmt_trace_hook);
     SCHEDULER.start(cp.SYST, SYSTICKS_PER_SCHED_TICK);
 }
 
-static RABBIT_STACK: Stack<1024> = Stack::new();
-
 /// Our 'rabbit' task
 fn rabbits() -> ! {
     let mut counter = 0.0;
@@ -42,18 +40,16 @@ fn rabbits() -> ! {
     }
 }
 
-static HAMSTER_STACK: Stack<1024> = Stack::new();
-
 /// Our 'hamster' task
 fn hamsters() -> ! {
     loop {
         defmt::info!("Hamster! (back in 10)");
+        defmt::info!("CPU usage: {}", pets::cpu_usage::<3>());
+        pets::dump_tasks();
         pets::delay(10);
     }
 }
 
-static CAT_STACK: Stack<1024> = Stack::new();
-
 /// Our 'cat' task
 fn cats() -> ! {
     loop {
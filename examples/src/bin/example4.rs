@@ -6,29 +6,17 @@
 #![no_std]
 #![no_main]
 
-use pets::{Scheduler, Stack, Task};
-
 use pets_examples as _;
 
 const SYSTICKS_PER_SCHED_TICK: u32 = 100_000;
 
-static SCHEDULER: Scheduler = Scheduler::new({
-    static TASK_LIST: [Task; 3] = [
-        Task::new(rabbits, {
-            static STACK: Stack<1024> = Stack::new();
-            &STACK
-        }),
-        Task::new(hamsters, {
-            static STACK: Stack<1024> = Stack::new();
-            &STACK
-        }),
-        Task::new(cats, {
-            static STACK: Stack<1024> = Stack::new();
-            &STACK
-        }),
+pets::tasks! {
+    static SCHEDULER = [
+        Task { entry: rabbits, stack: 1024 },
+        Task { entry: hamsters, stack: 1024 },
+        Task { entry: cats, stack: 1024 },
     ];
-    &TASK_LIST
-});
+}
 
 #[cortex_m_rt::entry]
 fn main() -> ! {
@@ -4,6 +4,12 @@
 
 use defmt_semihosting as _;
 
+#[cfg(feature = "trustzone")]
+mod secure;
+
+#[cfg(feature = "trustzone")]
+pub use secure::{NON_SECURE_IRQ, SECURE_CONTEXT_API, secure_wait_for_tick};
+
 /// Called when a panic occurs.
 ///
 /// Logs the panic to defmt and then crashes the CPU.
@@ -22,7 +28,12 @@ unsafe fn HardFault(info: &cortex_m_rt::ExceptionFrame) -> ! {
     cortex_m::asm::udf();
 }
 
-// Log scheduler ticks in the defmt logs
-defmt::timestamp!("{=u32:010} {}", pets::now(), pets::task_id());
+// Log scheduler ticks, and the time in timer counts, in the defmt logs
+defmt::timestamp!(
+    "{=u32:010} {=u64:012} {}",
+    pets::now(),
+    pets::now_precise(),
+    pets::task_id()
+);
 
 // End of File
@@ -0,0 +1,437 @@
+//! A tiny Secure world, so the examples can run pets in the Non-secure world
+//!
+//! This is only enough to run the examples on QEMU's `mps2-an505` machine.
+//! It is all assembly language, because the Secure world cannot run any of
+//! the Non-secure code - including `core`. The linker script,
+//! `memory_mps2tz_ns.x`, puts it in the Secure alias of SSRAM1.
+//!
+//! At reset we:
+//!
+//! * Make the Non-secure part of SSRAM1 (for code) and all of SSRAM2 (for
+//!   data) Non-secure, in both the memory protection controllers and the SAU
+//! * Make our Secure gateways Non-secure Callable
+//! * Let the Non-secure world use the FPU
+//! * Give the Non-secure world one interrupt, [`NON_SECURE_IRQ`]
+//! * Boot the Non-secure image, which is the example as usual
+//!
+//! The Secure world then offers four functions to the Non-secure world. The
+//! first three are what `pets::trustzone::SecureContextApi` needs. The fourth
+//! is a 'secure service' for the tasks to call.
+
+// Copyright (c) 2025 Ferrous Systems
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use pets::trustzone::SecureContextApi;
+
+// The Non-secure Callable functions in our Secure world
+unsafe extern "C" {
+    /// Allocate a Secure stack, and return its context handle (or zero)
+    fn secure_context_alloc(stack_size: u32) -> u32;
+
+    /// Save `PSP_S` into the given context
+    fn secure_context_save(context: u32);
+
+    /// Load `PSP_S` and `PSPLIM_S` from the given context
+    fn secure_context_load(context: u32);
+
+    /// Keep `value` on the Secure stack until the next SysTick, then return it
+    ///
+    /// This gives the scheduler a chance to pre-empt the caller whilst it is
+    /// in the Secure world. If the caller gets something else back, its
+    /// Secure stack was trampled on.
+    ///
+    /// # Safety
+    ///
+    /// The calling task must have a Secure context - see
+    /// `pets::trustzone::allocate_secure_context`.
+    pub fn secure_wait_for_tick(value: u32) -> u32;
+}
+
+/// How pets should talk to our Secure world
+pub static SECURE_CONTEXT_API: SecureContextApi = SecureContextApi {
+    alloc: secure_context_alloc,
+    save: secure_context_save,
+    load: secure_context_load,
+};
+
+/// How many tasks can have a Secure context
+const MAX_CONTEXTS: u32 = 8;
+
+/// How much Secure RAM we have for task stacks, in bytes
+const STACK_POOL_SIZE: u32 = 16 * 1024;
+
+/// The Security Controller's Non-secure Callable Configuration Register
+const NSCCFG: u32 = 0x5008_0014;
+
+/// The Memory Protection Controller for SSRAM1
+const MPC_SSRAM1: u32 = 0x5800_7000;
+
+/// The Memory Protection Controller for SSRAM2
+const MPC_SSRAM2: u32 = 0x5800_8000;
+
+/// The Secure Attribution Unit's Control Register
+const SAU_CTRL: u32 = 0xE000_EDD0;
+
+/// The Non-secure Access Control Register
+const NSACR: u32 = 0xE000_ED8C;
+
+/// The first Interrupt Target Non-secure Register
+const NVIC_ITNS0: u32 = 0xE000_E380;
+
+/// An interrupt the Non-secure world can have, to pend from software
+///
+/// None of the peripherals we use raise it.
+pub const NON_SECURE_IRQ: u16 = 14;
+
+/// The Non-secure alias of the Vector Table Offset Register
+const VTOR_NS: u32 = 0xE002_ED08;
+
+/// The Non-secure alias of the SysTick Current Value Register
+const SYST_CVR_NS: u32 = 0xE002_E018;
+
+core::arch::global_asm!(
+    r#"
+    // Clear the scratch registers, so we don't leak Secure state, and
+    // return to the Non-secure world. Leaves r0 alone, as it holds the
+    // return value (if any).
+    .macro secure_return
+    movs    r1, #0
+    movs    r2, #0
+    movs    r3, #0
+    mov     r12, r1
+    bxns    lr
+    .endm
+
+    //
+    // The Secure vector table. The CPU boots from here.
+    //
+
+    .pushsection .secure_vector_table, "a"
+    .word   __secure_stack_top
+    .word   secure_reset
+    // NMI, HardFault, MemManage, BusFault, UsageFault and SecureFault
+    .word   secure_fault
+    .word   secure_fault
+    .word   secure_fault
+    .word   secure_fault
+    .word   secure_fault
+    .word   secure_fault
+
+    .section .secure_text, "ax"
+
+    // Set up the Secure world, and boot the Non-secure world
+    .type   secure_reset, %function
+    .thumb_func
+secure_reset:
+    // Let the SAU make part of the Secure code alias Non-secure Callable
+    ldr     r0, ={nsccfg}
+    ldr     r1, [r0]
+    orr     r1, r1, #1
+    str     r1, [r0]
+
+    // Give the Non-secure world SSRAM1 from 1 MiB upwards, for code...
+    ldr     r0, ={mpc_ssram1}
+    ldr     r1, =0x00100000
+    ldr     r2, =0x00400000
+    bl      secure_mpc_set_ns
+
+    // ...and all of SSRAM2, for data
+    ldr     r0, ={mpc_ssram2}
+    movs    r1, #0
+    ldr     r2, =0x00200000
+    bl      secure_mpc_set_ns
+
+    // r0 = the SAU registers
+    ldr     r0, ={sau_ctrl}
+
+    // SAU region 0 is Non-secure code
+    movs    r1, #0
+    str     r1, [r0, #0x8]
+    ldr     r1, =0x00100000
+    str     r1, [r0, #0xC]
+    ldr     r1, =(0x003FFFE0 | 1)
+    str     r1, [r0, #0x10]
+
+    // SAU region 1 is Non-secure data
+    movs    r1, #1
+    str     r1, [r0, #0x8]
+    ldr     r1, =0x28000000
+    str     r1, [r0, #0xC]
+    ldr     r1, =(0x281FFFE0 | 1)
+    str     r1, [r0, #0x10]
+
+    // SAU region 2 is our Secure gateways, which are Non-secure Callable
+    movs    r1, #2
+    str     r1, [r0, #0x8]
+    ldr     r1, =__secure_gateways_start
+    str     r1, [r0, #0xC]
+    ldr     r1, =__secure_gateways_end
+    subs    r1, r1, #32
+    orr     r1, r1, #3
+    str     r1, [r0, #0x10]
+
+    // Turn the SAU on
+    movs    r1, #1
+    str     r1, [r0]
+    dsb
+    isb
+
+    // Let the Non-secure world use the FPU (CP10 and CP11)
+    ldr     r0, ={nsacr}
+    ldr     r1, [r0]
+    orr     r1, r1, #0xC00
+    str     r1, [r0]
+
+    // Give the Non-secure world an interrupt of its own
+    ldr     r0, ={nvic_itns0}
+    ldr     r1, =(1 << {non_secure_irq})
+    str     r1, [r0]
+
+    // Nothing has been allocated yet
+    ldr     r0, =secure_context_count
+    movs    r1, #0
+    str     r1, [r0]
+    ldr     r0, =secure_stack_next
+    ldr     r1, =secure_stack_pool
+    str     r1, [r0]
+
+    // Secure functions called by Non-secure tasks run on PSP_S. Until a
+    // task loads its own Secure context, it gets a small default stack.
+    ldr     r0, =secure_default_stack
+    msr     psplim, r0
+    ldr     r0, =secure_default_stack_top
+    msr     psp, r0
+    movs    r0, #2
+    msr     control, r0
+    isb
+
+    // Point the Non-secure world at its vector table, and jump to its
+    // reset handler on its Main Stack
+    ldr     r0, =__vector_table
+    ldr     r1, ={vtor_ns}
+    str     r0, [r1]
+    ldr     r1, [r0]
+    msr     msp_ns, r1
+    ldr     r1, [r0, #4]
+    bic     r1, r1, #1
+    bxns    r1
+    .size   secure_reset, . - secure_reset
+
+    // Make part of a block of SSRAM Non-secure
+    //
+    // r0 is the address of the MPC, r1 the offset of the first byte and r2
+    // the offset just past the last byte.
+    .type   secure_mpc_set_ns, %function
+    .thumb_func
+secure_mpc_set_ns:
+    // Turn off auto-increment, as we set BLK_IDX ourselves
+    ldr     r3, [r0]
+    bic     r3, r3, #0x100
+    str     r3, [r0]
+    // r3 = log2(block size)
+    ldr     r3, [r0, #0x14]
+    adds    r3, r3, #5
+    // r1 = first block, r2 = last block + 1
+    lsrs    r1, r1, r3
+    lsrs    r2, r2, r3
+1:
+    cmp     r1, r2
+    bhs     2f
+    // Select the LUT word for block r1, and set its bit
+    lsrs    r4, r1, #5
+    str     r4, [r0, #0x18]
+    ldr     r5, [r0, #0x1C]
+    and     r6, r1, #31
+    movs    r7, #1
+    lsls    r7, r7, r6
+    orrs    r5, r5, r7
+    str     r5, [r0, #0x1C]
+    adds    r1, r1, #1
+    b       1b
+2:
+    bx      lr
+    .size   secure_mpc_set_ns, . - secure_mpc_set_ns
+
+    // Something went wrong in the Secure world, so tell QEMU to exit
+    // with a failure
+    .type   secure_fault, %function
+    .thumb_func
+secure_fault:
+    // SYS_EXIT, with ADP_Stopped_RunTimeErrorUnknown
+    movs    r0, #0x18
+    ldr     r1, =0x20023
+    bkpt    0xAB
+    b       secure_fault
+    .size   secure_fault, . - secure_fault
+
+    // Allocate a Secure stack of at least r0 bytes, and return a context
+    // handle for it in r0 (or zero if we can't)
+    .type   secure_context_alloc_impl, %function
+    .thumb_func
+secure_context_alloc_impl:
+    // Round the size up to a multiple of 8 bytes, if it could possibly fit
+    ldr     r3, ={stack_pool_size}
+    cmp     r0, r3
+    bhi     2f
+    adds    r0, r0, #7
+    bic     r0, r0, #7
+    // r12 = the bottom of the new stack, r0 = the top
+    ldr     r3, =secure_stack_next
+    ldr     r12, [r3]
+    add     r0, r0, r12
+    ldr     r1, =secure_stack_pool_end
+    cmp     r0, r1
+    bhi     2f
+    // r1 = the number of contexts so far
+    ldr     r2, =secure_context_count
+    ldr     r1, [r2]
+    cmp     r1, #{max_contexts}
+    bhs     2f
+    // It fits, so take the space and the context
+    str     r0, [r3]
+    adds    r1, r1, #1
+    str     r1, [r2]
+    // The context for handle r1 lives at index r1 - 1
+    ldr     r3, =secure_context_table
+    add     r3, r3, r1, lsl #3
+    str     r0, [r3, #-8]
+    str     r12, [r3, #-4]
+    mov     r0, r1
+    b       3f
+2:
+    movs    r0, #0
+3:
+    secure_return
+    .size   secure_context_alloc_impl, . - secure_context_alloc_impl
+
+    // Save PSP_S into the context with the handle in r0
+    .type   secure_context_save_impl, %function
+    .thumb_func
+secure_context_save_impl:
+    ldr     r1, =secure_context_count
+    ldr     r1, [r1]
+    subs    r0, r0, #1
+    cmp     r0, r1
+    bhs     1f
+    ldr     r1, =secure_context_table
+    mrs     r2, psp
+    str     r2, [r1, r0, lsl #3]
+1:
+    movs    r0, #0
+    secure_return
+    .size   secure_context_save_impl, . - secure_context_save_impl
+
+    // Load PSP_S and PSPLIM_S from the context with the handle in r0
+    .type   secure_context_load_impl, %function
+    .thumb_func
+secure_context_load_impl:
+    ldr     r1, =secure_context_count
+    ldr     r1, [r1]
+    subs    r0, r0, #1
+    cmp     r0, r1
+    bhs     1f
+    ldr     r1, =secure_context_table
+    add     r1, r1, r0, lsl #3
+    ldrd    r2, r3, [r1]
+    // Clear the limit first, so the new PSP_S can't fall below the old one
+    movs    r0, #0
+    msr     psplim, r0
+    msr     psp, r2
+    msr     psplim, r3
+1:
+    movs    r0, #0
+    secure_return
+    .size   secure_context_load_impl, . - secure_context_load_impl
+
+    // Keep r0 on our stack until SysTick reloads, and then return it
+    .type   secure_wait_for_tick_impl, %function
+    .thumb_func
+secure_wait_for_tick_impl:
+    push    {{r0, lr}}
+    ldr     r1, ={syst_cvr_ns}
+    ldr     r2, [r1]
+1:
+    ldr     r3, [r1]
+    cmp     r3, r2
+    mov     r2, r3
+    bls     1b
+    pop     {{r0, lr}}
+    secure_return
+    .size   secure_wait_for_tick_impl, . - secure_wait_for_tick_impl
+
+    .ltorg
+
+    //
+    // The Secure gateways - the only way in from the Non-secure world
+    //
+
+    .section .secure_gateways, "ax"
+
+    .global secure_context_alloc
+    .type   secure_context_alloc, %function
+    .thumb_func
+secure_context_alloc:
+    sg
+    b.w     secure_context_alloc_impl
+
+    .global secure_context_save
+    .type   secure_context_save, %function
+    .thumb_func
+secure_context_save:
+    sg
+    b.w     secure_context_save_impl
+
+    .global secure_context_load
+    .type   secure_context_load, %function
+    .thumb_func
+secure_context_load:
+    sg
+    b.w     secure_context_load_impl
+
+    .global secure_wait_for_tick
+    .type   secure_wait_for_tick, %function
+    .thumb_func
+secure_wait_for_tick:
+    sg
+    b.w     secure_wait_for_tick_impl
+
+    //
+    // Secure RAM
+    //
+
+    .section .secure_bss, "aw", %nobits
+    .balign 8
+    // PSP_S and PSPLIM_S for each context
+secure_context_table:
+    .space  {max_contexts} * 8
+    // How many contexts we have handed out
+secure_context_count:
+    .space  4
+    // The bottom of the free space in the stack pool
+secure_stack_next:
+    .space  4
+    // The stack for tasks without a Secure context
+secure_default_stack:
+    .space  256
+secure_default_stack_top:
+    // Where task stacks come from
+secure_stack_pool:
+    .space  {stack_pool_size}
+secure_stack_pool_end:
+
+    .popsection
+    "#,
+    nsccfg = const NSCCFG,
+    mpc_ssram1 = const MPC_SSRAM1,
+    mpc_ssram2 = const MPC_SSRAM2,
+    sau_ctrl = const SAU_CTRL,
+    nsacr = const NSACR,
+    nvic_itns0 = const NVIC_ITNS0,
+    non_secure_irq = const NON_SECURE_IRQ,
+    vtor_ns = const VTOR_NS,
+    syst_cvr_ns = const SYST_CVR_NS,
+    max_contexts = const MAX_CONTEXTS,
+    stack_pool_size = const STACK_POOL_SIZE,
+);
+
+// End of File
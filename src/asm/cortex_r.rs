@@ -0,0 +1,151 @@
+//! Armv7-R and Armv8-R (AArch32) code
+
+use crate::port;
+
+/// The mode bits for System mode, which tasks run in
+const SYS_MODE: u32 = 0x1F;
+
+/// The mode bits for IRQ mode
+const IRQ_MODE: u32 = 0x12;
+
+/// The mode bits for Supervisor mode
+const SVC_MODE: u32 = 0x13;
+
+/// Push the FPU state, below the integer registers
+///
+/// We push FPSCR with a padding word (whatever is in r0), so the stack stays
+/// 8-byte aligned. The `.fpu` directive works around
+/// <https://github.com/rust-lang/rust/issues/127269>.
+#[cfg(all(arm_abi = "eabihf", not(target_feature = "d32")))]
+macro_rules! save_fpu {
+    () => {
+        ".fpu vfpv3-d16\nvpush {{d0-d15}}\nvmrs r1, fpscr\npush {{r0, r1}}"
+    };
+}
+
+/// Push the FPU state, below the integer registers
+///
+/// We push FPSCR with a padding word (whatever is in r0), so the stack stays
+/// 8-byte aligned. The `.fpu` directive works around
+/// <https://github.com/rust-lang/rust/issues/127269>.
+#[cfg(all(arm_abi = "eabihf", target_feature = "d32"))]
+macro_rules! save_fpu {
+    () => {
+        ".fpu vfpv3\nvpush {{d16-d31}}\nvpush {{d0-d15}}\nvmrs r1, fpscr\npush {{r0, r1}}"
+    };
+}
+
+/// Without an FPU, there's nothing to push
+#[cfg(arm_abi = "eabi")]
+macro_rules! save_fpu {
+    () => {
+        ""
+    };
+}
+
+/// Pop the FPU state pushed by `save_fpu!`
+#[cfg(all(arm_abi = "eabihf", not(target_feature = "d32")))]
+macro_rules! restore_fpu {
+    () => {
+        "pop {{r0, r1}}\nvmsr fpscr, r1\nvpop {{d0-d15}}"
+    };
+}
+
+/// Pop the FPU state pushed by `save_fpu!`
+#[cfg(all(arm_abi = "eabihf", target_feature = "d32"))]
+macro_rules! restore_fpu {
+    () => {
+        "pop {{r0, r1}}\nvmsr fpscr, r1\nvpop {{d0-d15}}\nvpop {{d16-d31}}"
+    };
+}
+
+/// Without an FPU, there's nothing to pop
+#[cfg(arm_abi = "eabi")]
+macro_rules! restore_fpu {
+    () => {
+        ""
+    };
+}
+
+/// IRQ Handler for Armv7-R and Armv8-R
+///
+/// This replaces the default IRQ handler from `cortex-r-rt`. It is called by
+/// the hardware, in IRQ mode, when an interrupt fires.
+///
+/// We push the return address and the saved CPSR onto the interrupted task's
+/// System mode stack, then switch to System mode and push everything else.
+/// We then go back to IRQ mode, so the handlers run on the IRQ stack, and
+/// call [`port::irq_handler`] with the task's stack pointer. It gives us back
+/// the stack pointer of the task to resume - which is a different task if we
+/// switched - and we pop everything from that stack and return.
+///
+/// It is a naked function because we do not want the compiler pushing
+/// anything else to the stack and re-using registers containing precious task
+/// state.
+#[unsafe(no_mangle)]
+#[unsafe(naked)]
+unsafe extern "C" fn _asm_irq_handler() {
+    core::arch::naked_asm!(
+        // LR_irq is four bytes past where we need to return to
+        "sub     lr, lr, #4",
+        // Push LR_irq and SPSR_irq onto the System mode stack
+        "srsdb   sp!, #{sys_mode}",
+        // Now push everything else, from System mode
+        "cps     #{sys_mode}",
+        "push    {{r0-r12, lr}}",
+        save_fpu!(),
+        // r0 = the task's stack pointer
+        "mov     r0, sp",
+        // Run the handlers on the IRQ stack
+        "cps     #{irq_mode}",
+        "bl      {irq_handler}",
+        // r0 = the stack pointer of the task to resume
+        "cps     #{sys_mode}",
+        "mov     sp, r0",
+        restore_fpu!(),
+        "pop     {{r0-r12, lr}}",
+        // Pop the PC and CPSR, which returns to the task
+        "rfeia   sp!",
+        sys_mode = const SYS_MODE,
+        irq_mode = const IRQ_MODE,
+        irq_handler = sym port::irq_handler,
+    );
+}
+
+/// SVC Handler for Armv7-R and Armv8-R
+///
+/// This replaces the default SVC handler from `cortex-r-rt`. A task executes
+/// an `SVC` instruction when it wants to switch to another task, because we
+/// can only change the task state from an exception handler.
+///
+/// It works exactly like [`_asm_irq_handler`], except that LR_svc is already
+/// the address to return to, and it calls [`port::svc_handler`] on the SVC
+/// stack. Taking the SVC exception masks IRQs, just like taking an IRQ does,
+/// so we can't be switched out half-way through switching.
+#[unsafe(no_mangle)]
+#[unsafe(naked)]
+unsafe extern "C" fn _asm_svc_handler() {
+    core::arch::naked_asm!(
+        // Push LR_svc and SPSR_svc onto the System mode stack
+        "srsdb   sp!, #{sys_mode}",
+        // Now push everything else, from System mode
+        "cps     #{sys_mode}",
+        "push    {{r0-r12, lr}}",
+        save_fpu!(),
+        // r0 = the task's stack pointer
+        "mov     r0, sp",
+        // Run the handler on the SVC stack
+        "cps     #{svc_mode}",
+        "bl      {svc_handler}",
+        // r0 = the stack pointer of the task to resume
+        "cps     #{sys_mode}",
+        "mov     sp, r0",
+        restore_fpu!(),
+        "pop     {{r0-r12, lr}}",
+        // Pop the PC and CPSR, which returns to the task
+        "rfeia   sp!",
+        sys_mode = const SYS_MODE,
+        svc_mode = const SVC_MODE,
+        svc_handler = sym port::svc_handler,
+    );
+}
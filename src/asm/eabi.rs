@@ -1,6 +1,6 @@
 //! Armv7-M EABI code
 
-use crate::{Scheduler, Task, scheduler};
+use crate::{Scheduler, Task, port, scheduler};
 
 /// PendSV Handler for Armv7-M or Armv8-M Mainline EABI
 ///
@@ -67,9 +67,22 @@ unsafe extern "C" fn PendSV() {
 
     1:
 
+    // Tell the scheduler we are switching tasks. This is a normal function
+    // call, so it can trash r0-r3, r12 and LR - we have either stacked those
+    // already or we are about to restore them from the next task. It returns
+    // the ID of the next task in r0. We must use that, and not re-read
+    // `next_task`, because an interrupt may have changed `next_task` since.
+    bl    {task_switch_hook}
+
+    // r1 = the address of the Scheduler object
+    ldr    r1, ={scheduler_ptr}
+    ldr    r1, [r1]
+
+    // r3 = the task list pointer
+    ldr    r3, [r1, {task_list_offset}]
+
     // r2 = the next task byte offset
-    ldr     r2, [r1, {next_task_offset}]
-    lsl     r2, {task_size_bits}
+    lsl     r2, r0, {task_size_bits}
 
     // r0 = the stack pointer from the task object
     ldr     r0, [r3, r2]
@@ -86,8 +99,8 @@ unsafe extern "C" fn PendSV() {
     // r1 holds the scheduler object's address
     //
 
-    // copy the next task id to the current task id
-    ldr     r2, [r1, {next_task_offset}]
+    // r2 = the next task ID, from its byte offset, and make it the current task
+    lsr     r2, {task_size_bits}
     str     r2, [r1, {current_task_offset}]
 
     //
@@ -97,8 +110,8 @@ unsafe extern "C" fn PendSV() {
     bx       lr
     "#,
     scheduler_ptr = sym scheduler::SCHEDULER_PTR,
+    task_switch_hook = sym port::task_switch_hook,
     current_task_offset = const Scheduler::CURRENT_TASK_OFFSET,
-    next_task_offset = const Scheduler::NEXT_TASK_OFFSET,
     task_list_offset = const Scheduler::TASK_LIST_OFFSET,
     task_size_bits = const Task::SIZE_BITS,
     );
@@ -1,6 +1,6 @@
 //! Armv6-M EABI code
 
-use crate::{Scheduler, Task, scheduler};
+use crate::{Scheduler, Task, port, scheduler};
 
 /// PendSV Handler for Armv6-M or Armv8-M Baseline EABI
 ///
@@ -80,9 +80,28 @@ unsafe extern "C" fn PendSV() {
 
     1:
 
+    // sp = the handler stack pointer, so we can make a function call
+    mov     sp, r12
+
+    // Tell the scheduler we are switching tasks. This is a normal function
+    // call, so it can trash r0-r3, r12 and LR - we have either stacked those
+    // already or we are about to restore them from the next task. It returns
+    // the ID of the next task in r0. We must use that, and not re-read
+    // `next_task`, because an interrupt may have changed `next_task` since.
+    bl      {task_switch_hook}
+
+    // r1 = the address of the Scheduler object
+    ldr     r1, ={scheduler_ptr}
+    ldr     r1, [r1]
+
+    // r3 = the task list pointer
+    ldr     r3, [r1, {task_list_offset}]
+
+    // r12 = the handler stack pointer
+    mov     r12, sp
+
     // r2 = the next task byte offset
-    ldr     r2, [r1, {next_task_offset}]
-    lsls    r2, {task_size_bits}
+    lsls    r2, r0, {task_size_bits}
 
     // sp = the stack pointer from the task object
     ldr     r0, [r3, r2]
@@ -111,8 +130,8 @@ unsafe extern "C" fn PendSV() {
     // r1 holds the scheduler object's address
     //
 
-    // copy the next task id to the current task id
-    ldr     r2, [r1, {next_task_offset}]
+    // r2 = the next task ID, from its byte offset, and make it the current task
+    lsrs    r2, {task_size_bits}
     str     r2, [r1, {current_task_offset}]
 
     //
@@ -122,8 +141,8 @@ unsafe extern "C" fn PendSV() {
     bx       lr
     "#,
     scheduler_ptr = sym scheduler::SCHEDULER_PTR,
+    task_switch_hook = sym port::task_switch_hook,
     current_task_offset = const Scheduler::CURRENT_TASK_OFFSET,
-    next_task_offset = const Scheduler::NEXT_TASK_OFFSET,
     task_list_offset = const Scheduler::TASK_LIST_OFFSET,
     task_size_bits = const Task::SIZE_BITS,
     );
@@ -1,8 +1,8 @@
-//! Armv7-M EABIHF code
+//! Armv7-M, Armv8-M Mainline and Armv8.1-M EABIHF code
 
-use crate::{Scheduler, Task, scheduler};
+use crate::{Scheduler, Task, port, scheduler};
 
-/// PendSV Handler for Armv7-M or Armv8-M Mainline EABIHF
+/// PendSV Handler for Armv7-M, Armv8-M Mainline or Armv8.1-M EABIHF
 ///
 /// This is the task switch code. It is called by hardware when the PendSV bit
 /// is set and all other interrupts have finished.
@@ -18,6 +18,13 @@ use crate::{Scheduler, Task, scheduler};
 /// restore PC, LR, R12, R3, R2, R1, and R0 from the new tasks PSP (along with
 /// the low FPU state, if required), and so the new task will resume.
 ///
+/// This also works on Armv8.1-M with the M-profile Vector Extension (Helium),
+/// like the Cortex-M55. The vector registers Q0-Q7 are the same storage as
+/// S0-S31, and the hardware stacks VPR along with FPSCR and the low FPU
+/// registers, in what was a reserved word on Armv7-M. If the hardware only
+/// reserved space for that state (lazy stacking), our `vstmdb` makes it fill
+/// the space in before it runs.
+///
 /// It is a naked function because we do not want the compiler pushing
 /// anything else to the stack and re-using registers containing precious task
 /// state.
@@ -81,9 +88,22 @@ unsafe extern "C" fn PendSV() {
 
     1:
 
+    // Tell the scheduler we are switching tasks. This is a normal function
+    // call, so it can trash r0-r3, r12 and LR - we have either stacked those
+    // already or we are about to restore them from the next task. It returns
+    // the ID of the next task in r0. We must use that, and not re-read
+    // `next_task`, because an interrupt may have changed `next_task` since.
+    bl     {task_switch_hook}
+
+    // r1 = the address of the Scheduler object
+    ldr     r1, ={scheduler_ptr}
+    ldr     r1, [r1]
+
+    // r3 = the task list pointer
+    ldr     r3, [r1, {task_list_offset}]
+
     // r2 = the next task byte offset
-    ldr      r2, [r1, {next_task_offset}]
-    lsl      r2, {task_size_bits}
+    lsl      r2, r0, {task_size_bits}
 
     // r0 = the stack pointer from the task object
     ldr      r0, [r3, r2]
@@ -107,8 +127,8 @@ unsafe extern "C" fn PendSV() {
     // r1 holds the scheduler object's address
     //
 
-    // copy the next task id to the current task id
-    ldr      r2, [r1, {next_task_offset}]
+    // r2 = the next task ID, from its byte offset, and make it the current task
+    lsr      r2, {task_size_bits}
     str      r2, [r1, {current_task_offset}]
 
     //
@@ -118,8 +138,8 @@ unsafe extern "C" fn PendSV() {
     bx       lr
     "#,
     scheduler_ptr = sym scheduler::SCHEDULER_PTR,
+    task_switch_hook = sym port::task_switch_hook,
     current_task_offset = const Scheduler::CURRENT_TASK_OFFSET,
-    next_task_offset = const Scheduler::NEXT_TASK_OFFSET,
     task_list_offset = const Scheduler::TASK_LIST_OFFSET,
     task_size_bits = const Task::SIZE_BITS,
     );
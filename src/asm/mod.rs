@@ -1,16 +1,24 @@
 //! Appropriate assembly language routines for the architecture
 
 #[cfg(all(
+    pets_port = "cortex-m",
     arm_abi = "eabi",
     any(arm_architecture = "v6-m", arm_architecture = "v8-m.base")
 ))]
 mod eabi_v6;
 
 #[cfg(all(
+    pets_port = "cortex-m",
     arm_abi = "eabi",
     not(any(arm_architecture = "v6-m", arm_architecture = "v8-m.base"))
 ))]
 mod eabi;
 
-#[cfg(arm_abi = "eabihf")]
+#[cfg(all(pets_port = "cortex-m", arm_abi = "eabihf"))]
 mod eabihf;
+
+#[cfg(pets_port = "cortex-r")]
+mod cortex_r;
+
+#[cfg(pets_port = "riscv")]
+mod riscv;
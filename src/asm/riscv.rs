@@ -0,0 +1,117 @@
+//! RISC-V trap vector
+
+use crate::port::{self, FRAME_WORDS, TRAP_STACK, TRAP_STACK_SIZE};
+
+// Trap vector for 32-bit RISC-V, in machine mode
+//
+// Every trap comes here. We push the interrupted task's registers onto its
+// stack, move to the trap stack, and then call the port's trap handler with
+// the task's stack pointer. The handler gives us back the stack pointer of
+// the task to resume - which is a different task if we switched - and we
+// restore that task's registers and return to it.
+//
+// This is not a naked Rust function because `mtvec` needs it to be 4-byte
+// aligned, and only `global_asm!` lets us say so.
+//
+// See `port::riscv::FRAME_WORDS` for the frame layout.
+core::arch::global_asm!(r#"
+    .section .text.pets_trap, "ax"
+    .global pets_trap
+    .balign 4
+pets_trap:
+    addi    sp, sp, -({frame_words} * 4)
+
+    // Stack every register that is not the same for every task
+    sw      x1, 1 * 4(sp)
+    sw      x5, 5 * 4(sp)
+    sw      x6, 6 * 4(sp)
+    sw      x7, 7 * 4(sp)
+    sw      x8, 8 * 4(sp)
+    sw      x9, 9 * 4(sp)
+    sw      x10, 10 * 4(sp)
+    sw      x11, 11 * 4(sp)
+    sw      x12, 12 * 4(sp)
+    sw      x13, 13 * 4(sp)
+    sw      x14, 14 * 4(sp)
+    sw      x15, 15 * 4(sp)
+    sw      x16, 16 * 4(sp)
+    sw      x17, 17 * 4(sp)
+    sw      x18, 18 * 4(sp)
+    sw      x19, 19 * 4(sp)
+    sw      x20, 20 * 4(sp)
+    sw      x21, 21 * 4(sp)
+    sw      x22, 22 * 4(sp)
+    sw      x23, 23 * 4(sp)
+    sw      x24, 24 * 4(sp)
+    sw      x25, 25 * 4(sp)
+    sw      x26, 26 * 4(sp)
+    sw      x27, 27 * 4(sp)
+    sw      x28, 28 * 4(sp)
+    sw      x29, 29 * 4(sp)
+    sw      x30, 30 * 4(sp)
+    sw      x31, 31 * 4(sp)
+
+    // Stack where the task was, and its interrupt state
+    csrr    t0, mepc
+    sw      t0, 0 * 4(sp)
+    csrr    t0, mstatus
+    sw      t0, 2 * 4(sp)
+
+    // a0 = the task's stack pointer
+    mv      a0, sp
+
+    // Move to the trap stack, so tasks don't need room for the handler
+    la      sp, {trap_stack}
+    li      t0, {trap_stack_size}
+    add     sp, sp, t0
+
+    // a0 = the stack pointer of the task to resume
+    call    {trap_handler}
+    mv      sp, a0
+
+    // Restore where the task was, and its interrupt state
+    lw      t0, 0 * 4(sp)
+    csrw    mepc, t0
+    lw      t0, 2 * 4(sp)
+    csrw    mstatus, t0
+
+    // Restore everything else
+    lw      x1, 1 * 4(sp)
+    lw      x5, 5 * 4(sp)
+    lw      x6, 6 * 4(sp)
+    lw      x7, 7 * 4(sp)
+    lw      x8, 8 * 4(sp)
+    lw      x9, 9 * 4(sp)
+    lw      x10, 10 * 4(sp)
+    lw      x11, 11 * 4(sp)
+    lw      x12, 12 * 4(sp)
+    lw      x13, 13 * 4(sp)
+    lw      x14, 14 * 4(sp)
+    lw      x15, 15 * 4(sp)
+    lw      x16, 16 * 4(sp)
+    lw      x17, 17 * 4(sp)
+    lw      x18, 18 * 4(sp)
+    lw      x19, 19 * 4(sp)
+    lw      x20, 20 * 4(sp)
+    lw      x21, 21 * 4(sp)
+    lw      x22, 22 * 4(sp)
+    lw      x23, 23 * 4(sp)
+    lw      x24, 24 * 4(sp)
+    lw      x25, 25 * 4(sp)
+    lw      x26, 26 * 4(sp)
+    lw      x27, 27 * 4(sp)
+    lw      x28, 28 * 4(sp)
+    lw      x29, 29 * 4(sp)
+    lw      x30, 30 * 4(sp)
+    lw      x31, 31 * 4(sp)
+
+    addi    sp, sp, ({frame_words} * 4)
+
+    // return to the task
+    mret
+    "#,
+    frame_words = const FRAME_WORDS,
+    trap_stack = sym TRAP_STACK,
+    trap_stack_size = const TRAP_STACK_SIZE,
+    trap_handler = sym port::trap_handler,
+);
@@ -0,0 +1,63 @@
+//! Holds [`block_on`], which runs a future on a pets task
+
+// Copyright (c) 2025 Ferrous Systems
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use core::{
+    pin::pin,
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+};
+
+use crate::{Scheduler, TaskId};
+
+/// The functions behind our [`Waker`]s
+///
+/// The data pointer isn't really a pointer - it's the index of the task to
+/// wake.
+static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake, drop);
+
+/// Make a [`RawWaker`] which wakes the given task
+fn raw_waker(task_id: TaskId) -> RawWaker {
+    RawWaker::new(core::ptr::without_provenance(task_id.index()), &VTABLE)
+}
+
+/// Copy one of our wakers
+fn clone(data: *const ()) -> RawWaker {
+    RawWaker::new(data, &VTABLE)
+}
+
+/// Wake the task that one of our wakers belongs to
+fn wake(data: *const ()) {
+    if let Some(scheduler) = Scheduler::get_scheduler() {
+        scheduler.wake(TaskId::new(data.addr()));
+    }
+}
+
+/// Our wakers own nothing, so there is nothing to drop
+fn drop(_data: *const ()) {}
+
+/// Run a future to completion, on the current task
+///
+/// The task sleeps whenever the future is waiting, and is polled again when
+/// the future's waker is woken, or on the next tick. Panics if it is not
+/// called from a task.
+pub fn block_on<F>(future: F) -> F::Output
+where
+    F: Future,
+{
+    let scheduler = Scheduler::get_scheduler().expect("block_on needs a running scheduler");
+    let task_id = scheduler.current_task_id();
+    // SAFETY: Our waker's data is just a task index, which is always safe to
+    // copy, and our vtable functions are all thread-safe.
+    let waker = unsafe { Waker::from_raw(raw_waker(task_id)) };
+    let mut context = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+        scheduler.yield_until_woken();
+    }
+}
+
+// End of File
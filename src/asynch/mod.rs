@@ -0,0 +1,80 @@
+//! Running `async` code inside a pets task
+//!
+//! Call [`block_on`] from a task to run a future on that task. Whilst the
+//! future is waiting, the task is parked and other tasks run. When the
+//! future's [`Waker`] is woken - by another task, or by an interrupt handler
+//! - the task is unparked, and if the CPU was idle it runs straight away.
+//!
+//! Every tick unparks every task, so a future is polled at least once per
+//! tick even if nobody wakes it. That's how [`delay`] works, and it means a
+//! [`Queue`] or [`Semaphore`] with several waiters only needs to remember
+//! the most recent one - the others notice on the next tick.
+
+// Copyright (c) 2025 Ferrous Systems
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod executor;
+mod queue;
+mod semaphore;
+
+use core::{future::poll_fn, task::Poll, task::Waker};
+
+pub use executor::block_on;
+pub use queue::Queue;
+pub use semaphore::Semaphore;
+
+use crate::Scheduler;
+
+/// Wait for at least the given period, measured in timer ticks
+///
+/// Like [`crate::delay`], `delay(0).await` gives up the CPU until the next
+/// tick.
+pub async fn delay(ticks: u32) {
+    let scheduler = Scheduler::get_scheduler().unwrap();
+    let start = scheduler.now();
+    let mut polled = false;
+    poll_fn(|_cx| {
+        // always wait at least once, so delay(0) is a yield
+        let elapsed = scheduler.now().wrapping_sub(start);
+        if polled && elapsed >= ticks {
+            Poll::Ready(())
+        } else {
+            polled = true;
+            // The next tick will poll us again
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+/// Remembers the [`Waker`] of the future waiting on something
+///
+/// Only use it from within a critical section.
+struct WakerSlot {
+    /// The waker of the most recent future to wait, if any
+    waker: Option<Waker>,
+}
+
+impl WakerSlot {
+    /// Make an empty slot
+    const fn new() -> WakerSlot {
+        WakerSlot { waker: None }
+    }
+
+    /// Remember the given waker, replacing any other
+    fn register(&mut self, waker: &Waker) {
+        match &self.waker {
+            Some(old_waker) if old_waker.will_wake(waker) => {}
+            _ => self.waker = Some(waker.clone()),
+        }
+    }
+
+    /// Take the waker out of the slot, if there was one
+    ///
+    /// Wake it once you have left your critical section.
+    fn take(&mut self) -> Option<Waker> {
+        self.waker.take()
+    }
+}
+
+// End of File
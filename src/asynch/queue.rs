@@ -0,0 +1,193 @@
+//! Holds the [`Queue`] type, for sending values between tasks
+
+// Copyright (c) 2025 Ferrous Systems
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use core::{cell::UnsafeCell, future::poll_fn, mem::MaybeUninit, task::Poll};
+
+use super::WakerSlot;
+use crate::port;
+
+/// A fixed-size queue of up to `N` values of type `T`
+///
+/// Any task or interrupt handler can send or receive, but only the most
+/// recent sender and receiver to wait are woken straight away.
+pub struct Queue<T, const N: usize> {
+    /// The queue state, only touched within a critical section
+    inner: UnsafeCell<QueueInner<T, N>>,
+}
+
+/// The contents of a [`Queue`]
+struct QueueInner<T, const N: usize> {
+    /// The values in the queue, some of which are initialised
+    buffer: [MaybeUninit<T>; N],
+    /// The index of the oldest value
+    head: usize,
+    /// How many values are in the queue
+    len: usize,
+    /// A sender waiting for space
+    sender: WakerSlot,
+    /// A receiver waiting for a value
+    receiver: WakerSlot,
+}
+
+impl<T, const N: usize> Queue<T, N> {
+    /// Make a new, empty, queue
+    pub const fn new() -> Queue<T, N> {
+        assert!(N > 0, "Queue needs space for at least one value");
+        Queue {
+            inner: UnsafeCell::new(QueueInner {
+                buffer: [const { MaybeUninit::uninit() }; N],
+                head: 0,
+                len: 0,
+                sender: WakerSlot::new(),
+                receiver: WakerSlot::new(),
+            }),
+        }
+    }
+
+    /// Add a value to the back of the queue, if there is space
+    ///
+    /// Gives you the value back if the queue is full.
+    pub fn try_send(&self, value: T) -> Result<(), T> {
+        let receiver = self.with_inner(|inner| {
+            inner.push(value)?;
+            Ok(inner.receiver.take())
+        })?;
+        if let Some(waker) = receiver {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    /// Add a value to the back of the queue, waiting for space if it is full
+    pub async fn send(&self, value: T) {
+        let mut value = Some(value);
+        poll_fn(|cx| {
+            let Some(unsent) = value.take() else {
+                return Poll::Ready(());
+            };
+            let result = self.with_inner(|inner| match inner.push(unsent) {
+                Ok(()) => Ok(inner.receiver.take()),
+                Err(unsent) => {
+                    inner.sender.register(cx.waker());
+                    Err(unsent)
+                }
+            });
+            match result {
+                Ok(receiver) => {
+                    if let Some(waker) = receiver {
+                        waker.wake();
+                    }
+                    Poll::Ready(())
+                }
+                Err(unsent) => {
+                    value = Some(unsent);
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+
+    /// Take the value from the front of the queue, if there is one
+    pub fn try_recv(&self) -> Option<T> {
+        let (value, sender) = self.with_inner(|inner| {
+            let value = inner.pop()?;
+            Some((value, inner.sender.take()))
+        })?;
+        if let Some(waker) = sender {
+            waker.wake();
+        }
+        Some(value)
+    }
+
+    /// Take the value from the front of the queue, waiting for one if it is
+    /// empty
+    pub async fn recv(&self) -> T {
+        poll_fn(|cx| {
+            let result = self.with_inner(|inner| match inner.pop() {
+                Some(value) => Some((value, inner.sender.take())),
+                None => {
+                    inner.receiver.register(cx.waker());
+                    None
+                }
+            });
+            match result {
+                Some((value, sender)) => {
+                    if let Some(waker) = sender {
+                        waker.wake();
+                    }
+                    Poll::Ready(value)
+                }
+                None => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    /// How many values are in the queue right now
+    pub fn len(&self) -> usize {
+        self.with_inner(|inner| inner.len)
+    }
+
+    /// Is the queue empty right now?
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get at the queue state, within a critical section
+    fn with_inner<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut QueueInner<T, N>) -> R,
+    {
+        port::critical_section(|| {
+            // SAFETY: We are in a critical section, so nothing else can be
+            // touching the queue state
+            f(unsafe { &mut *self.inner.get() })
+        })
+    }
+}
+
+impl<T, const N: usize> Default for Queue<T, N> {
+    fn default() -> Self {
+        Queue::new()
+    }
+}
+
+impl<T, const N: usize> QueueInner<T, N> {
+    /// Add a value to the back, or give it back if we are full
+    fn push(&mut self, value: T) -> Result<(), T> {
+        if self.len == N {
+            return Err(value);
+        }
+        self.buffer[(self.head + self.len) % N].write(value);
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Take the value from the front, if there is one
+    fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        // SAFETY: The `len` values from `head` onwards are initialised, and
+        // we stop counting this one as initialised straight away.
+        let value = unsafe { self.buffer[self.head].assume_init_read() };
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(value)
+    }
+}
+
+impl<T, const N: usize> Drop for QueueInner<T, N> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+// SAFETY: We only touch the queue state within a critical section, and
+// values of type `T` can be sent from one task to another.
+unsafe impl<T, const N: usize> Sync for Queue<T, N> where T: Send {}
+
+// End of File
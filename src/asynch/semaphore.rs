@@ -0,0 +1,103 @@
+//! Holds the [`Semaphore`] type, for counting events or sharing resources
+
+// Copyright (c) 2025 Ferrous Systems
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use core::{cell::UnsafeCell, future::poll_fn, task::Poll};
+
+use super::WakerSlot;
+use crate::port;
+
+/// A counting semaphore
+///
+/// Any task or interrupt handler can release a permit, but only the most
+/// recent task to wait for one is woken straight away.
+pub struct Semaphore {
+    /// The semaphore state, only touched within a critical section
+    inner: UnsafeCell<SemaphoreInner>,
+}
+
+/// The state of a [`Semaphore`]
+struct SemaphoreInner {
+    /// How many permits are available
+    permits: usize,
+    /// A task waiting for a permit
+    waiter: WakerSlot,
+}
+
+impl Semaphore {
+    /// Make a new semaphore, with the given number of permits available
+    pub const fn new(permits: usize) -> Semaphore {
+        Semaphore {
+            inner: UnsafeCell::new(SemaphoreInner {
+                permits,
+                waiter: WakerSlot::new(),
+            }),
+        }
+    }
+
+    /// Take a permit, if one is available
+    pub fn try_acquire(&self) -> bool {
+        self.with_inner(|inner| inner.take_permit())
+    }
+
+    /// Take a permit, waiting for one if none are available
+    pub async fn acquire(&self) {
+        poll_fn(|cx| {
+            self.with_inner(|inner| {
+                if inner.take_permit() {
+                    Poll::Ready(())
+                } else {
+                    inner.waiter.register(cx.waker());
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+
+    /// Give back a permit, waking the task waiting for one
+    pub fn release(&self) {
+        let waiter = self.with_inner(|inner| {
+            inner.permits += 1;
+            inner.waiter.take()
+        });
+        if let Some(waker) = waiter {
+            waker.wake();
+        }
+    }
+
+    /// How many permits are available right now
+    pub fn permits(&self) -> usize {
+        self.with_inner(|inner| inner.permits)
+    }
+
+    /// Get at the semaphore state, within a critical section
+    fn with_inner<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut SemaphoreInner) -> R,
+    {
+        port::critical_section(|| {
+            // SAFETY: We are in a critical section, so nothing else can be
+            // touching the semaphore state
+            f(unsafe { &mut *self.inner.get() })
+        })
+    }
+}
+
+impl SemaphoreInner {
+    /// Take a permit, if there is one
+    fn take_permit(&mut self) -> bool {
+        if self.permits == 0 {
+            false
+        } else {
+            self.permits -= 1;
+            true
+        }
+    }
+}
+
+// SAFETY: We only touch the semaphore state within a critical section
+unsafe impl Sync for Semaphore {}
+
+// End of File
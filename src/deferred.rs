@@ -0,0 +1,137 @@
+//! Holds the [`Deferred`] type, for moving work out of interrupt handlers
+
+// Copyright (c) 2025 Ferrous Systems
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{Scheduler, TaskId, asynch::Queue, port};
+
+/// A queue of work for a worker task, which interrupt handlers can post to
+///
+/// Keep your interrupt handlers short by having them [`post`](Deferred::post)
+/// a function and an argument. A worker task, which does nothing but call
+/// [`run`](Deferred::run), calls the function for you. Posting switches to
+/// the worker straight away, so the work runs as soon as the interrupt
+/// handler returns, ahead of every other task.
+///
+/// ```rust
+/// static DEFERRED: pets::Deferred<4> = pets::Deferred::new();
+///
+/// pets::tasks! {
+///     static SCHEDULER = [
+///         Task { entry: worker, stack: 1024, name: "worker" },
+///         Task { entry: idler, stack: 512 },
+///     ];
+/// }
+///
+/// fn worker() -> ! {
+///     DEFERRED.run()
+/// }
+///
+/// fn idler() -> ! {
+///     loop {
+///         pets::delay(1);
+///     }
+/// }
+///
+/// fn handle_rx(byte: usize) {
+///     // Runs in the worker task, so it can take as long as it likes
+///     assert_eq!(byte, 0x55);
+/// }
+///
+/// // In your interrupt handler:
+/// # let sim = pets::sim::Sim::new(&SCHEDULER);
+/// # sim.interrupt(|| {
+/// DEFERRED.post(handle_rx, 0x55).unwrap();
+/// # });
+/// ```
+pub struct Deferred<const N: usize> {
+    /// The work waiting to be done
+    queue: Queue<Work, N>,
+    /// The index of the worker task, or `usize::MAX` if it hasn't started
+    worker: AtomicUsize,
+}
+
+/// We couldn't post work, because the queue was full
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DeferredFull;
+
+/// One function call, waiting for the worker task
+struct Work {
+    /// The function to call
+    function: fn(usize),
+    /// What to pass to the function
+    arg: usize,
+}
+
+impl<const N: usize> Deferred<N> {
+    /// Make a new, empty, work queue, with space for `N` items
+    pub const fn new() -> Deferred<N> {
+        Deferred {
+            queue: Queue::new(),
+            worker: AtomicUsize::new(usize::MAX),
+        }
+    }
+
+    /// Ask the worker task to call `function(arg)`
+    ///
+    /// You can call this from a task or from an interrupt handler. If the
+    /// worker task is running, we switch to it straight away (or, in an
+    /// interrupt handler, as soon as the handler returns).
+    pub fn post(&self, function: fn(usize), arg: usize) -> Result<(), DeferredFull> {
+        self.queue
+            .try_send(Work { function, arg })
+            .map_err(|_| DeferredFull)?;
+        let worker = self.worker.load(Ordering::Acquire);
+        if worker != usize::MAX
+            && let Some(scheduler) = Scheduler::get_scheduler()
+        {
+            scheduler.wake_now(TaskId::new(worker));
+        }
+        Ok(())
+    }
+
+    /// How many items are waiting for the worker task right now
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Is the work queue empty right now?
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Do the posted work, forever
+    ///
+    /// Call this from the entry function of your worker task. Only one task
+    /// may call this for each work queue.
+    pub fn run(&self) -> ! {
+        let scheduler = Scheduler::get_scheduler().unwrap();
+        let task_id = scheduler.current_task_id();
+        let claimed = port::critical_section(|| {
+            if self.worker.load(Ordering::Relaxed) != usize::MAX {
+                return false;
+            }
+            self.worker.store(task_id.index(), Ordering::Release);
+            true
+        });
+        assert!(claimed, "Deferred work queue already has a worker task");
+        debug!("Task {} is a deferred worker", task_id);
+        loop {
+            while let Some(work) = self.queue.try_recv() {
+                (work.function)(work.arg);
+            }
+            scheduler.yield_until_woken();
+        }
+    }
+}
+
+impl<const N: usize> Default for Deferred<N> {
+    fn default() -> Self {
+        Deferred::new()
+    }
+}
+
+// End of File
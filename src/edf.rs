@@ -0,0 +1,166 @@
+//! Earliest-deadline-first scheduling, for tasks with deadlines
+//!
+//! A task with a deadline does one job per period. Its first job is released
+//! when the scheduler starts, and each job must be finished (by calling
+//! [`wait_for_next_period`](crate::wait_for_next_period)) within the task's
+//! relative deadline. With the [`EarliestDeadlineFirst`] policy, of the tasks
+//! that aren't parked and whose current job has been released, the one whose
+//! job has the earliest deadline runs.
+//!
+//! Whatever the policy, a job misses its deadline if it is still running when
+//! its deadline tick arrives, or if it couldn't even start before then
+//! because the previous job overran.
+
+// Copyright (c) 2025 Ferrous Systems
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::{RoundRobin, SchedPolicy, SchedView, TaskId, TaskSelection};
+
+/// The period and deadline of a task, and how its current job is going
+pub(crate) struct Deadline {
+    /// How many ticks between job releases, or zero if there is no deadline
+    period: u32,
+    /// How many ticks after its release each job must be finished
+    relative: u32,
+    /// The tick at which the current job was released
+    release: AtomicU32,
+    /// How many jobs have missed their deadline
+    misses: AtomicU32,
+}
+
+impl Deadline {
+    /// A task with no deadline
+    pub(crate) const fn none() -> Deadline {
+        Deadline::new(0, 0)
+    }
+
+    /// A task which does one job every `period` ticks, each of which must be
+    /// done within `relative` ticks
+    pub(crate) const fn new(period: u32, relative: u32) -> Deadline {
+        Deadline {
+            period,
+            relative,
+            release: AtomicU32::new(0),
+            misses: AtomicU32::new(0),
+        }
+    }
+
+    /// Does this task have a deadline?
+    pub(crate) fn exists(&self) -> bool {
+        self.period != 0
+    }
+
+    /// Get the tick at which the current job is released
+    pub(crate) fn release(&self) -> u32 {
+        self.release.load(Ordering::Relaxed)
+    }
+
+    /// Get the tick at which the current job must be finished
+    pub(crate) fn absolute(&self) -> u32 {
+        self.release
+            .load(Ordering::Relaxed)
+            .wrapping_add(self.relative)
+    }
+
+    /// How many jobs have missed their deadline?
+    pub(crate) fn misses(&self) -> u32 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// Count a miss if the current job is still running at its deadline
+    ///
+    /// Call this on every tick. Only call this from within a critical
+    /// section. Returns `true` if this job just missed its deadline.
+    pub(crate) fn check(&self, now: u32) -> bool {
+        if self.exists() && now == self.absolute() {
+            self.count_miss();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Finish the current job, and release the next one a period after it
+    ///
+    /// Only call this from within a critical section. Returns the tick at
+    /// which the next job is released, and whether that job has already
+    /// missed its deadline.
+    pub(crate) fn finish_job(&self, now: u32) -> (u32, bool) {
+        let release = self
+            .release
+            .load(Ordering::Relaxed)
+            .wrapping_add(self.period);
+        self.release.store(release, Ordering::Relaxed);
+        let late = ticks_until(self.absolute(), now) <= 0;
+        if late {
+            self.count_miss();
+        }
+        (release, late)
+    }
+
+    /// Add one to the number of missed deadlines
+    fn count_miss(&self) {
+        self.misses.store(
+            self.misses.load(Ordering::Relaxed).wrapping_add(1),
+            Ordering::Relaxed,
+        );
+    }
+}
+
+/// Tasks with deadlines run earliest-deadline-first
+///
+/// A task with a deadline doesn't wait for another task's time slice to end,
+/// if its job has an earlier deadline than the running task's. Tasks
+/// without a deadline only run when no task with a released job wants to,
+/// and take turns, like [`RoundRobin`].
+#[derive(Copy, Clone, Debug, Default)]
+pub struct EarliestDeadlineFirst;
+
+impl SchedPolicy for EarliestDeadlineFirst {
+    fn select(&self, view: &SchedView) -> TaskSelection {
+        select_next_task(view).unwrap_or_else(|| RoundRobin.select(view))
+    }
+
+    fn preempt(&self, view: &SchedView) -> bool {
+        matches!(select_next_task(view), Some(TaskSelection::NewTask(_)))
+    }
+}
+
+/// How many ticks from `now` until `tick`, which may be in the past
+pub(crate) fn ticks_until(tick: u32, now: u32) -> i32 {
+    tick.wrapping_sub(now) as i32
+}
+
+/// Pick the task with the earliest deadline, out of those that aren't parked
+/// and whose current job has been released
+///
+/// A task waiting for its next job is unparked by every tick, like any other
+/// waiting task, so we mustn't pick it until the job is released, even though
+/// that job's deadline may well be the earliest. The current task wins a
+/// tie, and otherwise ties go round-robin. Returns `None` if no task with a
+/// released job wants to run.
+///
+/// This only uses what [`SchedView`] shows every policy.
+pub(crate) fn select_next_task(view: &SchedView) -> Option<TaskSelection> {
+    let current_task = view.current_task().index();
+    let task_list = view.tasks();
+    let num_tasks = task_list.len();
+    let (selected_next_task, _) = (current_task..(current_task + num_tasks))
+        .map(|idx| idx % num_tasks)
+        .filter_map(|idx| {
+            let task = &task_list[idx];
+            let released = view.ticks_until(task.job_release()?) <= 0;
+            let until_deadline = view.ticks_until(task.job_deadline()?);
+            (!task.parked() && released).then_some((idx, until_deadline))
+        })
+        .min_by_key(|(_, until_deadline)| *until_deadline)?;
+    if selected_next_task == current_task {
+        Some(TaskSelection::CurrentTask)
+    } else {
+        Some(TaskSelection::NewTask(TaskId::new(selected_next_task)))
+    }
+}
+
+// End of File
@@ -0,0 +1,99 @@
+//! Holds the [`Delay`] type, which implements the embedded-hal traits
+
+// Copyright (c) 2025 Ferrous Systems
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::{SchedPolicy, Scheduler};
+
+/// Delays a task, letting other tasks run in the meantime
+///
+/// Whole ticks are spent in [`delay`](crate::delay) (or
+/// [`asynch::delay`](crate::asynch::delay)), and only the rest of the time is
+/// spent spinning on [`now_precise`](crate::now_precise). On a port which
+/// can't read its timer between ticks, the spin lasts until the next tick.
+///
+/// Only use this from within a task.
+#[derive(Copy, Clone, Debug)]
+pub struct Delay {
+    /// How fast the scheduler's timer counts (e.g. your CPU clock, for
+    /// SysTick)
+    counts_per_second: u32,
+}
+
+/// What to do next, whilst waiting for a deadline
+enum Wait {
+    /// The deadline has passed
+    Done,
+    /// The deadline is less than a tick away
+    Spin,
+    /// Sleep for this many ticks
+    Sleep(u32),
+}
+
+impl Delay {
+    /// Make a new [`Delay`], for a scheduler timer that counts at the given
+    /// rate
+    pub const fn new(counts_per_second: u32) -> Delay {
+        Delay { counts_per_second }
+    }
+
+    /// Work out when a delay of `ns` nanoseconds, starting now, ends
+    fn deadline(&self, scheduler: &Scheduler<dyn SchedPolicy>, ns: u32) -> u64 {
+        let counts = (u64::from(ns) * u64::from(self.counts_per_second)).div_ceil(1_000_000_000);
+        scheduler.now_precise() + counts
+    }
+
+    /// Work out how to wait for the given deadline
+    ///
+    /// Sleeping wakes us at a tick boundary, which is never past `end`.
+    fn next_wait(scheduler: &Scheduler<dyn SchedPolicy>, end: u64) -> Wait {
+        let now = scheduler.now_precise();
+        if now >= end {
+            return Wait::Done;
+        }
+        let remaining = end - now;
+        let counts_per_tick = u64::from(scheduler.counts_per_tick());
+        // The simulator's clock only moves in whole ticks, so spinning
+        // would never end
+        let ticks = if cfg!(pets_port = "sim") {
+            remaining.div_ceil(counts_per_tick)
+        } else {
+            remaining / counts_per_tick
+        };
+        if ticks == 0 {
+            Wait::Spin
+        } else {
+            Wait::Sleep(ticks as u32)
+        }
+    }
+}
+
+impl embedded_hal::delay::DelayNs for Delay {
+    fn delay_ns(&mut self, ns: u32) {
+        let scheduler = Scheduler::get_scheduler().unwrap();
+        let end = self.deadline(scheduler, ns);
+        loop {
+            match Self::next_wait(scheduler, end) {
+                Wait::Done => break,
+                Wait::Spin => core::hint::spin_loop(),
+                Wait::Sleep(ticks) => crate::delay(ticks),
+            }
+        }
+    }
+}
+
+impl embedded_hal_async::delay::DelayNs for Delay {
+    async fn delay_ns(&mut self, ns: u32) {
+        let scheduler = Scheduler::get_scheduler().unwrap();
+        let end = self.deadline(scheduler, ns);
+        loop {
+            match Self::next_wait(scheduler, end) {
+                Wait::Done => break,
+                Wait::Spin => core::hint::spin_loop(),
+                Wait::Sleep(ticks) => crate::asynch::delay(ticks).await,
+            }
+        }
+    }
+}
+
+// End of File
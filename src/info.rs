@@ -0,0 +1,105 @@
+//! Holds the [`TaskInfo`] and [`TaskState`] types
+
+// Copyright (c) 2025 Ferrous Systems
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::TaskId;
+
+/// What a task is currently doing
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TaskState {
+    /// The task is running right now
+    Running,
+    /// The task could run, but another task is running
+    Ready,
+    /// The task has nothing to do until the next tick
+    Parked,
+}
+
+/// A snapshot of the state of a task
+///
+/// See [`crate::tasks()`].
+#[derive(Clone, Debug)]
+pub struct TaskInfo {
+    /// The task's ID
+    pub id: TaskId,
+    /// The task's name, if it has one
+    pub name: Option<&'static str>,
+    /// What the task is currently doing
+    pub state: TaskState,
+    /// The task's stack pointer
+    ///
+    /// For a task that isn't running, this is where it was when the task
+    /// last stopped running.
+    pub stack_pointer: usize,
+    /// The most stack the task has ever used, in bytes
+    ///
+    /// This is `None` if we can't tell, e.g. in a simulation, where tasks
+    /// run on their thread's stack rather than on their
+    /// [`Stack`](crate::Stack).
+    pub stack_used: Option<usize>,
+    /// The size of the task's stack, in bytes
+    pub stack_size: usize,
+    /// How many times the task has been switched in
+    pub run_count: u32,
+    /// The tick count when the task was last switched in
+    pub last_run_tick: u32,
+    /// How many of the task's jobs have missed their deadline
+    #[cfg(feature = "edf")]
+    pub deadline_misses: u32,
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for TaskInfo {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(
+            fmt,
+            "T{=usize:03} {=str} {} sp=0x{=usize:08x} stack=",
+            self.id.index(),
+            self.name.unwrap_or("-"),
+            self.state,
+            self.stack_pointer
+        );
+        match self.stack_used {
+            Some(stack_used) => defmt::write!(fmt, "{=usize}", stack_used),
+            None => defmt::write!(fmt, "?"),
+        }
+        defmt::write!(
+            fmt,
+            "/{=usize} runs={=u32} last={=u32}",
+            self.stack_size,
+            self.run_count,
+            self.last_run_tick
+        );
+        #[cfg(feature = "edf")]
+        defmt::write!(fmt, " misses={=u32}", self.deadline_misses);
+    }
+}
+
+impl core::fmt::Display for TaskInfo {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            fmt,
+            "T{:03} {} {:?} sp=0x{:08x} stack=",
+            self.id.index(),
+            self.name.unwrap_or("-"),
+            self.state,
+            self.stack_pointer
+        )?;
+        match self.stack_used {
+            Some(stack_used) => write!(fmt, "{}", stack_used)?,
+            None => write!(fmt, "?")?,
+        }
+        write!(
+            fmt,
+            "/{} runs={} last={}",
+            self.stack_size, self.run_count, self.last_run_tick
+        )?;
+        #[cfg(feature = "edf")]
+        write!(fmt, " misses={}", self.deadline_misses)?;
+        Ok(())
+    }
+}
+
+// End of File
@@ -4,40 +4,173 @@
 //! multiple tasks to execute and it will execute each of them in turn.
 //!
 //! It currently only works on Arm Cortex-M - either Armv7-M, Armv7E-M or
-//! Armv8-M Main should be fine.
+//! Armv8-M Main should be fine. Armv8.1-M (e.g. Cortex-M55, with Helium)
+//! works too - build for `thumbv8m.main-none-eabihf` with `-C
+//! target-cpu=cortex-m55`. There are also ports for Arm Cortex-R
+//! (Armv7-R and Armv8-R), and for 32-bit RISC-V in machine mode (e.g.
+//! `riscv32imac-unknown-none-elf`). On other targets with `std` you can run
+//! tasks in a simulation - see `pets::sim`.
 //!
 //! It's basically an exercise in seeing just how small an RTOS kernel you
 //! could get away with, whilst still being somewhat useful.
 //!
+//! ## Interrupt Handlers
+//!
+//! Only tasks can wait, so [`delay`], [`Scheduler::yield_until_tick`],
+//! [`asynch::block_on`] and `Delay` panic if you call them from an interrupt
+//! handler. Interrupt handlers may call:
+//!
+//! * [`on_tick`], [`now`], [`now_precise`], [`task_id`], [`cpu_usage`] and
+//!   [`tasks()`]
+//! * `wake` on a `Waker` from [`asynch::block_on`]
+//! * `try_send`, `try_recv` and `len` on an [`asynch::Queue`]
+//! * `try_acquire`, `release` and `permits` on an [`asynch::Semaphore`]
+//! * `post`, `len` and `is_empty` on a [`Deferred`] work queue
+//!
+//! On Cortex-M CPUs with BASEPRI, you can use
+//! `pets::cortex_m::set_max_syscall_priority` to stop pets masking your most
+//! urgent interrupts. Those interrupts must not call pets at all.
+//!
+//! When the scheduler starts, it gives PendSV the least urgent priority and
+//! SysTick the priority set with `pets::cortex_m::set_kernel_priority`, and
+//! panics if those priorities don't fit with the max syscall priority.
+//!
+//! ## Cargo Features
+//!
+//! * `systick` (on by default) - on Cortex-M, drive the scheduler tick from
+//!   SysTick, using pets' own `SysTick` exception handler. Turn it off to
+//!   bring your own `TickSource` instead, and call `on_tick` from its
+//!   interrupt handler.
+//!
+//! * `defmt` (on by default) - log what the scheduler is doing with
+//!   `defmt`, and implement `defmt::Format` for our types.
+//!
+//! * `embedded-hal` - implement `DelayNs`, from both `embedded-hal` and
+//!   `embedded-hal-async`, for `pets::Delay`, so drivers can sleep without
+//!   hogging the CPU.
+//!
+//! * `edf` - give tasks deadlines, with `Task::with_deadline`, and count
+//!   the ones they miss. The `EarliestDeadlineFirst` policy runs them
+//!   earliest-deadline-first, ahead of tasks without a deadline.
+//!
+//! * `log` - log what the scheduler is doing with the `log` crate. Turn off
+//!   both this and `defmt` and the scheduler does no logging at all.
+//!
+//! * `trace` - lets you install a hook, with `set_trace_hook`, which is
+//!   called on every task switch, park, unpark and tick.
+//!
+//! * `trustzone` - for Armv8-M CPUs with TrustZone, when pets runs in the
+//!   Non-secure world. Tasks start in the Non-secure world, and can ask the
+//!   Secure firmware for their own Secure stack - see `pets::trustzone`.
+//!
 //! * Copyright (C) 2025 Ferrous Systems
 //! * SPDX-License-Identifier: GPL-3.0-or-later
 
-#![no_std]
+#![cfg_attr(target_os = "none", no_std)]
 #![deny(clippy::undocumented_unsafe_blocks)]
 #![deny(clippy::missing_docs_in_private_items)]
 #![deny(clippy::missing_safety_doc)]
 
+#[macro_use]
+mod logging;
+
+pub mod asynch;
+mod deferred;
+#[cfg(feature = "edf")]
+mod edf;
+#[cfg(feature = "embedded-hal")]
+mod hal;
+mod info;
+mod macros;
+mod policy;
+mod port;
 mod scheduler;
 mod stack;
+#[cfg(not(pets_port = "sim"))]
 mod stack_pusher;
 mod task;
+mod trace;
+mod usage;
 
 use core::cell::UnsafeCell;
 
+pub use deferred::{Deferred, DeferredFull};
+#[cfg(feature = "edf")]
+pub use edf::EarliestDeadlineFirst;
+#[cfg(feature = "embedded-hal")]
+pub use hal::Delay;
+pub use info::{TaskInfo, TaskState};
+pub use policy::{RoundRobin, SchedPolicy, SchedView};
 pub use scheduler::Scheduler;
+pub use scheduler::{TaskId, TaskSelection};
 pub use stack::Stack;
 pub use task::Task;
+pub use trace::TraceEvent;
+#[cfg(all(feature = "trace", feature = "defmt"))]
+pub use trace::defmt_trace_hook;
+#[cfg(feature = "trace")]
+pub use trace::{TraceHookFn, set_trace_hook};
+pub use usage::CpuUsage;
+
+#[cfg(pets_port = "cortex-m")]
+pub use port::TickSource;
 
-use scheduler::TaskId;
+#[cfg(not(pets_port = "sim"))]
 use stack_pusher::StackPusher;
 
+#[cfg(not(pets_port = "sim"))]
 mod asm;
 
+/// Running pets on Arm Cortex-M
+///
+/// See [`Scheduler::start`].
+#[cfg(pets_port = "cortex-m")]
+pub mod cortex_m {
+    pub use crate::port::set_kernel_priority;
+    #[cfg(pets_basepri)]
+    pub use crate::port::set_max_syscall_priority;
+}
+
+/// Running pets in the Non-secure world of an Armv8-M CPU with TrustZone
+///
+/// See [`allocate_secure_context`](trustzone::allocate_secure_context).
+#[cfg(all(pets_port = "cortex-m", feature = "trustzone"))]
+pub mod trustzone {
+    pub use crate::port::{
+        SecureContextApi, SecureContextError, allocate_secure_context, set_secure_context_api,
+    };
+}
+
+/// Running pets on Arm Cortex-R
+///
+/// See [`Scheduler::start`].
+#[cfg(pets_port = "cortex-r")]
+pub mod cortex_r {
+    #[cfg(arm_architecture = "v8-r")]
+    pub use crate::port::on_timer_interrupt;
+}
+
+/// Running pets on RISC-V
+///
+/// See [`Scheduler::start`].
+#[cfg(pets_port = "riscv")]
+pub mod riscv {
+    pub use crate::port::Clint;
+}
+
+/// Running pets on your computer, with a virtual clock
+///
+/// See [`Sim`](sim::Sim) and [`spin`](sim::spin).
+#[cfg(pets_port = "sim")]
+pub mod sim {
+    pub use crate::port::{Run, Sim, spin};
+}
+
 /// Delay a task for at least the given period, measured in timer ticks.
 ///
 /// Calling `delay(0)` is basically just a yield.
 pub fn delay(ticks: u32) {
-    defmt::trace!("Sleeping for {} ticks", ticks);
+    trace!("Sleeping for {} ticks", ticks);
     let scheduler = Scheduler::get_scheduler().unwrap();
     let start = scheduler.now();
     loop {
@@ -48,7 +181,35 @@ pub fn delay(ticks: u32) {
         if delta >= ticks {
             break;
         }
-        defmt::trace!("Task {} still sleeping...", task_id());
+        trace!("Task {} still sleeping...", task_id());
+    }
+}
+
+/// Wait for the start of this task's next period
+///
+/// Call this when the task has finished this period's job - see
+/// [`Task::with_deadline`]. If the job overran, and the next period has
+/// already started, this returns straight away.
+///
+/// Panics if the task has no deadline.
+#[cfg(feature = "edf")]
+pub fn wait_for_next_period() {
+    let scheduler = Scheduler::get_scheduler().unwrap();
+    let release = scheduler.finish_job();
+    trace!("Waiting for tick {}", release);
+    while edf::ticks_until(release, scheduler.now()) > 0 {
+        scheduler.yield_until_tick();
+    }
+}
+
+/// Tick the scheduler
+///
+/// Call this from the interrupt handler of whatever timer drives the
+/// scheduler tick. Any task switch happens once your handler returns. Does
+/// nothing if the scheduler isn't running.
+pub fn on_tick() {
+    if let Some(scheduler) = Scheduler::get_scheduler() {
+        scheduler.sched_tick();
     }
 }
 
@@ -61,6 +222,18 @@ pub fn now() -> u32 {
     }
 }
 
+/// Get the current time, in timer counts
+///
+/// This has better than tick resolution on most ports - see
+/// [`Scheduler::now_precise`]. Returns zero if the scheduler isn't running.
+pub fn now_precise() -> u64 {
+    if let Some(scheduler) = Scheduler::get_scheduler() {
+        scheduler.now_precise()
+    } else {
+        0
+    }
+}
+
 /// Get the currently running task ID
 pub fn task_id() -> TaskId {
     if let Some(scheduler) = Scheduler::get_scheduler() {
@@ -70,14 +243,32 @@ pub fn task_id() -> TaskId {
     }
 }
 
-/// Our SysTick Handler
+/// Get the CPU usage figures for the most recent usage window
 ///
-/// Tells the global scheduler that maybe its time to think about changing
-/// which task is running.
-#[unsafe(no_mangle)]
-extern "C" fn SysTick() {
-    let scheduler = Scheduler::get_scheduler().unwrap();
-    scheduler.sched_tick();
+/// There is room for the first `N` tasks, so make `N` at least as big as
+/// your task list. Returns `None` if the scheduler isn't running. See
+/// [`CpuUsage`] for details.
+pub fn cpu_usage<const N: usize>() -> Option<CpuUsage<N>> {
+    Scheduler::get_scheduler().map(|scheduler| scheduler.cpu_usage())
+}
+
+/// Get a snapshot of the state of every task, in task list order
+///
+/// Yields nothing if the scheduler isn't running.
+pub fn tasks() -> impl Iterator<Item = TaskInfo> {
+    Scheduler::get_scheduler()
+        .into_iter()
+        .flat_map(|scheduler| scheduler.tasks())
+}
+
+/// Print a table of every task, and its state, to the log
+///
+/// Does nothing if neither the `defmt` nor the `log` feature is enabled.
+pub fn dump_tasks() {
+    info!("Task dump @ tick {}:", now());
+    for task_info in tasks() {
+        info!("  {}", task_info);
+    }
 }
 
 // End of File
@@ -0,0 +1,49 @@
+//! Our internal logging macros
+//!
+//! Depending on the cargo features, these log with `defmt`, with `log`, with
+//! both, or not at all. Format strings must be understood by both crates, so
+//! stick to `{}`, `{:?}` and hints like `{:08x}`. Every argument must
+//! implement `defmt::Format` as well as `Display` (or `Debug`, for `{:?}`).
+//!
+//! With neither feature enabled, the arguments are still evaluated but the
+//! optimiser throws them away.
+
+// Copyright (c) 2025 Ferrous Systems
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+/// Log at the given level, with whichever logging crates are enabled
+macro_rules! log_at {
+    ($level:ident, $fmt:literal $(, $arg:expr)* $(,)?) => {{
+        #[cfg(feature = "defmt")]
+        defmt::$level!($fmt $(, $arg)*);
+        #[cfg(feature = "log")]
+        ::log::$level!($fmt $(, $arg)*);
+        #[cfg(not(any(feature = "defmt", feature = "log")))]
+        {
+            $(let _ = &$arg;)*
+        }
+    }};
+}
+
+/// Log an interesting event
+macro_rules! info {
+    ($($args:tt)*) => {
+        log_at!(info, $($args)*)
+    };
+}
+
+/// Log something which helps explain what the scheduler is doing
+macro_rules! debug {
+    ($($args:tt)*) => {
+        log_at!(debug, $($args)*)
+    };
+}
+
+/// Log every little detail
+macro_rules! trace {
+    ($($args:tt)*) => {
+        log_at!(trace, $($args)*)
+    };
+}
+
+// End of File
@@ -0,0 +1,106 @@
+//! Holds the [`tasks!`](crate::tasks) macro
+
+// Copyright (c) 2025 Ferrous Systems
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+/// Declare a [`Scheduler`](crate::Scheduler), its tasks, and their stacks
+///
+/// Each task gets its own [`Stack`](crate::Stack) of the given size, in
+/// bytes. You can leave out the `name` and the `quantum` (the task's time
+/// slice, in ticks - see [`Task::with_quantum`](crate::Task::with_quantum)),
+/// and put `#[cfg(...)]` on a task to only include it in some builds. With
+/// the `edf` feature, you can also give a task a `period` and a `deadline` -
+/// see `Task::with_deadline`. Add `.with_policy::<P>(policy)` after the
+/// list to use a different [`SchedPolicy`](crate::SchedPolicy) - the
+/// scheduler's type depends on the policy, so give the policy's type as well
+/// as its value.
+///
+/// The full syntax is below, where `(...)?` marks something you can leave
+/// out, and `(...)*` something you can have any number of. The fields of
+/// each task must come in this order, and the last comma in each list is
+/// optional.
+///
+/// ```text
+/// tasks! {
+///     (#[attribute])*
+///     (pub)? static NAME = [
+///         (#[cfg(...)])* Task {
+///             entry: function,
+///             stack: bytes,
+///             (name: "name",)?
+///             (quantum: ticks,)?
+///             (period: ticks, deadline: ticks,)?
+///         },
+///         ...
+///     ](.with_policy::<Type>(policy))?;
+/// }
+/// ```
+///
+/// ```rust
+/// pets::tasks! {
+///     /// Our scheduler
+///     static SCHEDULER = [
+///         Task { entry: blinky, stack: 1024, name: "blinky" },
+///         Task { entry: worker, stack: 512, quantum: 4 },
+///     ].with_policy::<pets::RoundRobin>(pets::RoundRobin);
+/// }
+///
+/// fn blinky() -> ! {
+///     loop {
+///         pets::delay(5);
+///     }
+/// }
+///
+/// fn worker() -> ! {
+///     loop {
+///         pets::delay(1);
+///     }
+/// }
+/// # let sim = pets::sim::Sim::new(&SCHEDULER);
+/// # sim.advance(10);
+/// # assert_eq!(pets::now(), 10);
+/// ```
+///
+/// It is a compile-time error to declare no tasks, or to give a task a stack
+/// that is too small or isn't a multiple of the stack alignment.
+#[macro_export]
+macro_rules! tasks {
+    (
+        $(#[$meta:meta])*
+        $vis:vis static $scheduler:ident = [
+            $(
+                $(#[$task_meta:meta])*
+                Task {
+                    entry: $entry:path,
+                    stack: $stack:expr
+                    $(, name: $name:expr)?
+                    $(, quantum: $quantum:expr)?
+                    $(, period: $period:expr, deadline: $deadline:expr)?
+                    $(,)?
+                }
+            ),* $(,)?
+        ] $(.with_policy::<$policy_ty:ty>($policy:expr))?;
+    ) => {
+        $(#[$meta])*
+        $vis static $scheduler: $crate::Scheduler$(<$policy_ty>)? = $crate::Scheduler::new({
+            /// How many tasks there are, in this build
+            const TASK_COUNT: usize = [$($(#[$task_meta])* (),)*].len();
+            static TASK_LIST: [$crate::Task; TASK_COUNT] = [
+                $(
+                    $(#[$task_meta])*
+                    $crate::Task::new($entry, {
+                        static STACK: $crate::Stack<{ $stack }> = $crate::Stack::new();
+                        &STACK
+                    })
+                    $(.with_name($name))?
+                    $(.with_quantum($quantum))?
+                    $(.with_deadline($period, $deadline))?,
+                )*
+            ];
+            &TASK_LIST
+        })
+        $(.with_policy::<$policy_ty>($policy))?;
+    };
+}
+
+// End of File
@@ -0,0 +1,117 @@
+//! Holds the [`SchedPolicy`] trait, and our round-robin policy
+
+// Copyright (c) 2025 Ferrous Systems
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::{Task, TaskId, TaskSelection, scheduler};
+
+/// Decides which task runs next
+///
+/// Give one to the scheduler with
+/// [`Scheduler::with_policy`](crate::Scheduler::with_policy). The scheduler
+/// always starts with the first task in the list, and after that it asks the
+/// policy whenever the current task waits, and on every tick once the current
+/// task has used up its time slice.
+///
+/// The policy is called from within a critical section, on a task or in the
+/// tick interrupt handler, so keep it quick.
+pub trait SchedPolicy: Sync {
+    /// Pick the task to run next
+    ///
+    /// Never pick a parked task. If the current task is parked, it is
+    /// waiting, so pick another task or [`TaskSelection::NoTasks`].
+    fn select(&self, view: &SchedView) -> TaskSelection;
+
+    /// Should the current task stop running before its time slice is up?
+    ///
+    /// Called on every tick whilst the current task still has some of its
+    /// time slice left. If this returns `true`, we call
+    /// [`select`](SchedPolicy::select) straight away. The default never
+    /// cuts a time slice short.
+    fn preempt(&self, view: &SchedView) -> bool {
+        let _ = view;
+        false
+    }
+}
+
+/// What a [`SchedPolicy`] can see of the scheduler
+///
+/// The tasks can't be changed through here - a policy only looks at them,
+/// and says which one should run.
+#[derive(Copy, Clone)]
+pub struct SchedView<'a> {
+    /// Which task is currently running
+    current_task: TaskId,
+    /// A fixed, static list of all our tasks
+    task_list: &'a [Task],
+    /// Current tick count
+    now: u32,
+    /// How many ticks are left in the current task's time slice
+    slice_left: u32,
+}
+
+impl<'a> SchedView<'a> {
+    /// Describe the scheduler's state to a policy
+    pub(crate) const fn new(
+        current_task: TaskId,
+        task_list: &'a [Task],
+        now: u32,
+        slice_left: u32,
+    ) -> SchedView<'a> {
+        SchedView {
+            current_task,
+            task_list,
+            now,
+            slice_left,
+        }
+    }
+
+    /// Get the ID of the task that is currently running
+    ///
+    /// It might be parked, if it is waiting.
+    pub const fn current_task(&self) -> TaskId {
+        self.current_task
+    }
+
+    /// Get every task, in task list order
+    ///
+    /// A [`TaskId`] is an index into this list.
+    pub const fn tasks(&self) -> &'a [Task] {
+        self.task_list
+    }
+
+    /// Get the current tick count
+    pub const fn now(&self) -> u32 {
+        self.now
+    }
+
+    /// Get how many ticks are left in the current task's time slice
+    ///
+    /// The slice starts at the task's [quantum](Task::quantum), and goes down
+    /// by one every tick.
+    pub const fn slice_left(&self) -> u32 {
+        self.slice_left
+    }
+
+    /// How many ticks from now until `tick`, which may be in the past
+    ///
+    /// The tick count wraps, so compare ticks with this rather than with `<`.
+    pub const fn ticks_until(&self, tick: u32) -> i32 {
+        tick.wrapping_sub(self.now) as i32
+    }
+}
+
+/// Tasks take turns, in task list order
+///
+/// Each task that isn't parked runs for its time slice, and then the next
+/// task that isn't parked gets a turn. This is the default policy.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RoundRobin;
+
+impl SchedPolicy for RoundRobin {
+    fn select(&self, view: &SchedView) -> TaskSelection {
+        scheduler::select_next_task(view.current_task().index(), view.tasks())
+    }
+}
+
+// End of File
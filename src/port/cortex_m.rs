@@ -0,0 +1,561 @@
+//! The port for Arm Cortex-M
+//!
+//! Any [`TickSource`] can generate the scheduler tick - with the `systick`
+//! feature, SysTick is one. We use PendSV to switch tasks. The PendSV
+//! handlers are in [`crate::asm`].
+//!
+//! By default, our critical sections mask every interrupt. On CPUs with
+//! BASEPRI, you can call [`set_max_syscall_priority`] so they only mask
+//! interrupts which might call pets. With the `trustzone`
+//! feature, PendSV also switches each task's Secure stack - see
+//! `port::trustzone`.
+
+// Copyright (c) 2025 Ferrous Systems
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use core::sync::atomic::{AtomicPtr, AtomicU8, Ordering};
+
+use crate::{SchedPolicy, Scheduler, StackPusher};
+
+/// This is the minimum stack we can support, because of the state we need to push
+///
+/// Make space for sixteen 32-bit registers in the task state, plus some
+/// headroom
+#[cfg(arm_abi = "eabi")]
+pub(crate) const MIN_STACK_SIZE: usize = (4 * 16) + 8;
+
+/// This is the minimum stack we can support, because of the state we need to push
+///
+/// Make space for sixteen 32-bit registers, thirty-two 32-bit FPU
+/// registers, plus FPU status register and VPR (or the reserved word where
+/// Armv8.1-M puts VPR), in the task state, plus some headroom
+#[cfg(arm_abi = "eabihf")]
+pub(crate) const MIN_STACK_SIZE: usize = (4 * 50) + 8;
+
+/// We paint task stacks before they start, so we can tell how much they used
+pub(crate) const PAINTS_STACKS: bool = true;
+
+/// The EXC_RETURN value a task starts with
+///
+/// Return to Thread Mode, on the Process Stack, with no FPU state. The S and ES
+/// bits are set, which is right for Armv7-M, and for Armv8-M when pets runs
+/// in the Secure world or there is no TrustZone.
+#[cfg(not(feature = "trustzone"))]
+const INITIAL_EXC_RETURN: u32 = 0xFFFF_FFFD;
+
+/// The EXC_RETURN value a task starts with
+///
+/// Return to Thread Mode, on the Non-secure Process Stack, with no FPU state.
+/// The S and ES bits are clear, because pets and the task are both in the
+/// Non-secure world, and DCRS is set as there are no Secure callee registers
+/// on the stack.
+#[cfg(feature = "trustzone")]
+const INITIAL_EXC_RETURN: u32 = 0xFFFF_FFBC;
+
+/// The value of the Processor Status Register when a task starts
+///
+/// The only bit we need to set is the T bit, to indicate that the
+/// task should run in Thumb mode (the only supported mode on Armv7-M)
+const DEFAULT_CPSR: u32 = 1 << 24;
+
+/// The priority we give SysTick, and expect any other tick source to have
+static KERNEL_PRIORITY: AtomicU8 = AtomicU8::new(LOWEST_PRIORITY);
+
+/// The least urgent exception priority
+///
+/// CPUs ignore the bottom bits they don't implement, so this is always the
+/// least urgent priority.
+const LOWEST_PRIORITY: u8 = 0xFF;
+
+/// The exception number of PendSV
+const PENDSV: usize = 14;
+
+/// The exception number of SysTick
+#[cfg(feature = "systick")]
+const SYSTICK: usize = 15;
+
+/// The most urgent priority of any interrupt which calls pets
+///
+/// Zero means we haven't been told, so critical sections mask everything.
+#[cfg(pets_basepri)]
+static MAX_SYSCALL_PRIORITY: AtomicU8 = AtomicU8::new(0);
+
+/// Our `timestamp` function, for the tick source we were started with
+///
+/// Null until the scheduler is started.
+static TIMESTAMP_FN: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+
+/// A timer which generates the scheduler tick
+///
+/// Its interrupt handler must call [`on_tick`](crate::on_tick) once per
+/// tick. With the `systick` feature, pets implements this for SysTick, and
+/// has a `SysTick` exception handler which does that for you.
+pub trait TickSource {
+    /// Start interrupting, once every `counts_per_tick` counts
+    ///
+    /// Called by [`Scheduler::start`] once the scheduler is registered, so
+    /// the interrupt handler can call [`on_tick`](crate::on_tick) straight
+    /// away.
+    fn start(&mut self, counts_per_tick: u32);
+
+    /// How many counts have gone by since the last tick
+    ///
+    /// This gives timestamps better than tick resolution. By default, we
+    /// only have tick resolution.
+    fn counts_since_tick() -> u32 {
+        0
+    }
+
+    /// Has the timer ticked, without its interrupt handler having run yet?
+    fn tick_pending() -> bool {
+        false
+    }
+}
+
+#[cfg(feature = "systick")]
+impl TickSource for cortex_m::peripheral::SYST {
+    fn start(&mut self, counts_per_tick: u32) {
+        // The reload value, one less than this, only has 24 bits
+        assert!(
+            (1..=0x0100_0000).contains(&counts_per_tick),
+            "SysTick can only count 1 to 0x0100_0000 counts per tick, not {:#x}",
+            counts_per_tick
+        );
+        // SAFETY: SysTick calls pets, so it must have the kernel priority
+        unsafe {
+            set_exception_priority(SYSTICK, KERNEL_PRIORITY.load(Ordering::Relaxed));
+        }
+        self.set_reload(counts_per_tick - 1);
+        self.set_clock_source(cortex_m::peripheral::syst::SystClkSource::Core);
+        self.clear_current();
+        self.enable_counter();
+        self.enable_interrupt();
+    }
+
+    fn counts_since_tick() -> u32 {
+        // SysTick counts down, from the reload value to zero
+        cortex_m::peripheral::SYST::get_reload()
+            .wrapping_sub(cortex_m::peripheral::SYST::get_current())
+    }
+
+    fn tick_pending() -> bool {
+        cortex_m::peripheral::SCB::is_pendst_pending()
+    }
+}
+
+impl<P: SchedPolicy + 'static> Scheduler<P> {
+    /// Run the scheduler
+    ///
+    /// You may only call this once, and you should call it from `fn main()`
+    /// once all your hardware is configured. We should be in Privileged
+    /// Thread mode on the Main stack.
+    ///
+    /// The scheduler ticks once every `counts_per_sched_tick` counts of the
+    /// `tick_source`. This is the length of a tick, not a SysTick reload
+    /// value: to tick every 100,000 CPU clocks, pass `100_000` (SysTick's
+    /// reload value is then `99_999`). For SysTick, it must be between 1 and
+    /// `0x0100_0000`.
+    ///
+    /// Panics if `counts_per_sched_tick` is zero.
+    pub fn start<T>(&self, mut tick_source: T, counts_per_sched_tick: u32) -> !
+    where
+        T: TickSource,
+    {
+        assert!(
+            counts_per_sched_tick != 0,
+            "A tick can't be zero counts long"
+        );
+        // remember where this object is - it cannot move because we do not exit this function
+        self.register(counts_per_sched_tick - 1);
+        TIMESTAMP_FN.store(
+            timestamp_from::<T> as fn(u32, u32) -> u32 as *mut (),
+            Ordering::Release,
+        );
+
+        // PendSV must only switch tasks once every other exception handler
+        // has finished, so it gets the least urgent priority.
+        // SAFETY: The scheduler hasn't started, so nothing is using PendSV
+        unsafe {
+            set_exception_priority(PENDSV, LOWEST_PRIORITY);
+        }
+        check_priorities();
+
+        // Must do this /after/ registering because the tick interrupt
+        // handler will use SCHEDULER_PTR
+        tick_source.start(counts_per_sched_tick);
+
+        // We need to push some empty state into each task stack
+        for (task_idx, task) in self.task_list().iter().enumerate() {
+            let old_stack_top = task.stack();
+            info!(
+                "Init task frame {} ({}), with stack @ 0x{:08x}",
+                task_idx,
+                task.name().unwrap_or("unnamed"),
+                old_stack_top as usize
+            );
+
+            // SAFETY: The task hasn't started yet, so nothing is using its stack
+            unsafe {
+                task.paint_stack();
+            }
+
+            // SAFETY: The task constructor does not let us make tasks with
+            // stacks that are too small.
+            let mut stack_pusher = unsafe { StackPusher::new(old_stack_top) };
+
+            // Standard Arm exception frame
+
+            // CPSR
+            stack_pusher.push(DEFAULT_CPSR);
+            // PC
+            stack_pusher.push(task.entry_fn() as usize as u32);
+            // LR
+            stack_pusher.push(0);
+            // R12
+            stack_pusher.push(0);
+            // R0-R3
+            stack_pusher.push(0);
+            stack_pusher.push(0);
+            stack_pusher.push(0);
+            stack_pusher.push(0);
+
+            // Additional task state we persist
+
+            // Extra copy of LR so we can check for FPU status. This copy does
+            // not have the FPU bit set, so we don't need to push an Extended
+            // Frame above, or the other 16 FPU registers, into the initial
+            // state. This will return us to Thread Mode, Process Stack.
+            stack_pusher.push(INITIAL_EXC_RETURN);
+
+            // R4 - R11
+            stack_pusher.push(0);
+            stack_pusher.push(0);
+            stack_pusher.push(0);
+            stack_pusher.push(0);
+            stack_pusher.push(0);
+            stack_pusher.push(0);
+            stack_pusher.push(0);
+            stack_pusher.push(0);
+
+            // Report how much space we used
+
+            debug!(
+                "Fini task frame {}, with stack @ 0x{:08x}",
+                task_idx,
+                stack_pusher.current() as usize
+            );
+
+            // Set task stack pointer to the last thing we pushed
+
+            // SAFETY: the pointer we are passing is a validly aligned stack pointer
+            unsafe {
+                task.set_stack(stack_pusher.current());
+            }
+        }
+
+        // Fire the PendSV exception - the PendSV handler will select a task
+        // to run and run it
+        debug!("Hit PendSV");
+        cortex_m::peripheral::SCB::set_pendsv();
+        // flush the pipeline to ensure the PendSV fires before we reach the end of this function
+        cortex_m::asm::isb();
+        // impossible to get here
+        unreachable!();
+    }
+}
+
+/// Set the priority of the scheduler tick
+///
+/// The priority is the value you give `NVIC::set_priority`, so lower numbers
+/// are more urgent. With the `systick` feature, [`Scheduler::start`] gives
+/// SysTick this priority. If you bring your own [`TickSource`], give its
+/// interrupt this priority yourself.
+///
+/// Call this before starting the scheduler. The default is the least urgent
+/// priority.
+pub fn set_kernel_priority(priority: u8) {
+    KERNEL_PRIORITY.store(priority, Ordering::Relaxed);
+}
+
+/// Panic if the exception priorities won't work with our critical sections
+///
+/// Call this once PendSV has the least urgent priority. Only CPUs with
+/// BASEPRI have anything to check.
+fn check_priorities() {
+    #[cfg(pets_basepri)]
+    {
+        let max_syscall_priority = MAX_SYSCALL_PRIORITY.load(Ordering::Relaxed);
+        if max_syscall_priority == 0 {
+            return;
+        }
+        // PendSV has the least urgent priority, so we can read back which
+        // priority bits this CPU has
+        let implemented = exception_priority(PENDSV);
+        if (max_syscall_priority & implemented) == 0 {
+            panic!(
+                "The max syscall priority {:#04x} is zero on a CPU with priority bits {:#04x}, so it masks nothing",
+                max_syscall_priority, implemented
+            );
+        }
+        // BASEPRI only compares the group priority, so we can't let any
+        // priority bits be sub-priority bits
+        // SAFETY: Reading AIRCR has no side effects
+        let aircr = unsafe { core::ptr::read_volatile(AIRCR as *const u32) };
+        let prigroup = (aircr >> 8) & 0x7;
+        let subpriority_mask = ((1u32 << (prigroup + 1)) - 1) as u8;
+        if (implemented & subpriority_mask) != 0 {
+            panic!(
+                "NVIC priority grouping PRIGROUP={} makes some of the priority bits {:#04x} sub-priority bits - pets needs them all to be group priority bits",
+                prigroup, implemented
+            );
+        }
+        let kernel_priority = KERNEL_PRIORITY.load(Ordering::Relaxed);
+        if (kernel_priority & implemented) < (max_syscall_priority & implemented) {
+            panic!(
+                "The kernel priority {:#04x} is more urgent than the max syscall priority {:#04x}",
+                kernel_priority, max_syscall_priority
+            );
+        }
+    }
+}
+
+/// Set the most urgent priority of any interrupt handler which calls pets
+///
+/// The priority is the value you give `NVIC::set_priority`, so lower numbers
+/// are more urgent. Once this is set, our critical sections raise BASEPRI to
+/// this priority, instead of masking every interrupt, so more urgent
+/// interrupts see no extra latency from pets. [`Scheduler::start`] gives
+/// PendSV the least urgent priority, and panics if the [kernel
+/// priority](set_kernel_priority) is more urgent than this, or if the NVIC
+/// priority grouping uses any priority bits for sub-priorities.
+///
+/// Interrupt handlers at this priority or less urgent may use the functions
+/// listed in the crate documentation. Any more urgent interrupt handler must
+/// not call pets at all - if it does, we panic (in debug builds - checking
+/// costs time in every critical section).
+///
+/// Call this before starting the scheduler. Zero (the default) means mask
+/// every interrupt.
+#[cfg(pets_basepri)]
+pub fn set_max_syscall_priority(priority: u8) {
+    MAX_SYSCALL_PRIORITY.store(priority, Ordering::Relaxed);
+}
+
+/// Run a closure with the scheduler's interrupts masked
+///
+/// That's every interrupt, unless [`set_max_syscall_priority`] was called.
+#[cfg(pets_basepri)]
+pub(crate) fn critical_section<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let max_syscall_priority = MAX_SYSCALL_PRIORITY.load(Ordering::Relaxed);
+    if max_syscall_priority == 0 {
+        return cortex_m::interrupt::free(|_| f());
+    }
+    #[cfg(debug_assertions)]
+    check_caller_priority(max_syscall_priority);
+    let old_basepri = cortex_m::register::basepri::read();
+    cortex_m::register::basepri_max::write(max_syscall_priority);
+    let result = f();
+    // SAFETY: We are putting BASEPRI back how we found it
+    unsafe {
+        cortex_m::register::basepri::write(old_basepri);
+    }
+    result
+}
+
+/// Run a closure with interrupts disabled
+#[cfg(not(pets_basepri))]
+pub(crate) fn critical_section<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    cortex_m::interrupt::free(|_| f())
+}
+
+/// Panic if the current exception is more urgent than the max syscall
+/// priority
+///
+/// Such an exception isn't masked by our critical sections, so it must not
+/// call pets. We only compare the priority bits the CPU implements, like
+/// BASEPRI does - so on a CPU with three priority bits, a max syscall priority
+/// of 0x50 is really 0x40, and an exception at priority 0x40 may call pets.
+#[cfg(all(pets_basepri, debug_assertions))]
+fn check_caller_priority(max_syscall_priority: u8) {
+    let priority = match active_exception() {
+        // Thread mode
+        0 => return,
+        // Reset, NMI and HardFault have negative priorities
+        exception @ 1..=3 => {
+            panic!(
+                "Exception {} called pets, but it can't be masked",
+                exception
+            )
+        }
+        exception => exception_priority(exception),
+    };
+    // PendSV has the least urgent priority, so we can read back which
+    // priority bits this CPU has
+    let implemented = exception_priority(PENDSV);
+    if (priority & implemented) < (max_syscall_priority & implemented) {
+        panic!(
+            "Exception {} (priority {}) called pets, but the max syscall priority is {}",
+            active_exception(),
+            priority,
+            max_syscall_priority
+        );
+    }
+}
+
+/// The address of the Application Interrupt and Reset Control Register
+#[cfg(pets_basepri)]
+const AIRCR: usize = 0xE000_ED0C;
+
+/// The address of the first System Handler Priority Register byte, for
+/// exception 4
+const SHPR_BASE: usize = 0xE000_ED18;
+
+/// The address of the first NVIC Interrupt Priority Register byte, for
+/// exception 16
+#[cfg(pets_basepri)]
+const NVIC_IPR_BASE: usize = 0xE000_E400;
+
+/// Get the priority of the given exception, which must be 4 or higher
+///
+/// Every CPU with BASEPRI allows byte accesses to the priority registers, so
+/// we read just the one byte.
+#[cfg(pets_basepri)]
+fn exception_priority(exception: usize) -> u8 {
+    let address = if exception < 16 {
+        SHPR_BASE + exception - 4
+    } else {
+        NVIC_IPR_BASE + exception - 16
+    };
+    // SAFETY: This is a priority register, which has no side effects when
+    // read
+    unsafe { core::ptr::read_volatile(address as *const u8) }
+}
+
+/// Set the priority of the given system exception, from 4 to 15
+///
+/// # Safety
+///
+/// Changing the priority of an exception which is running, or which pets
+/// relies on, can break things.
+unsafe fn set_exception_priority(exception: usize, priority: u8) {
+    let address = SHPR_BASE + exception - 4;
+    let word_address = (address & !3) as *mut u32;
+    let shift = (address & 3) * 8;
+    critical_section(|| {
+        // SAFETY: This is a System Handler Priority Register, and we only
+        // change the byte for this exception
+        unsafe {
+            let word = core::ptr::read_volatile(word_address);
+            let word = (word & !(0xFF << shift)) | (u32::from(priority) << shift);
+            core::ptr::write_volatile(word_address, word);
+        }
+    });
+}
+
+/// Get the number of the active exception, or zero in Thread mode
+fn active_exception() -> usize {
+    let ipsr: u32;
+    // SAFETY: Reading IPSR has no side effects
+    unsafe {
+        core::arch::asm!("mrs {0}, IPSR", out(reg) ipsr, options(nomem, nostack, preserves_flags));
+    }
+    (ipsr & 0x1FF) as usize
+}
+
+/// Are we in an exception handler, rather than a task?
+pub(crate) fn in_interrupt() -> bool {
+    active_exception() != 0
+}
+
+/// Ask for a switch to the scheduler's `next_task`
+///
+/// The switch happens in the PendSV handler, which runs once every other
+/// exception handler has finished.
+pub(crate) fn request_task_switch<P: SchedPolicy + ?Sized>(_scheduler: &Scheduler<P>) {
+    cortex_m::peripheral::SCB::set_pendsv();
+}
+
+/// Sleep until an interrupt occurs
+pub(crate) fn wait_for_interrupt() {
+    cortex_m::asm::wfi();
+    cortex_m::asm::isb();
+}
+
+/// Get a timestamp, measured in tick source counts
+///
+/// Give it the current tick count, and one less than the number of counts in
+/// a tick. Only call this from within a critical section.
+pub(crate) fn timestamp(ticks: u32, reload: u32) -> u32 {
+    let timestamp_ptr = TIMESTAMP_FN.load(Ordering::Acquire);
+    if timestamp_ptr.is_null() {
+        ticks.wrapping_mul(reload.wrapping_add(1))
+    } else {
+        // SAFETY: Only [`Scheduler::start`] writes to [`TIMESTAMP_FN`], and
+        // it always writes a valid `fn(u32, u32) -> u32`.
+        let timestamp_fn =
+            unsafe { core::mem::transmute::<*mut (), fn(u32, u32) -> u32>(timestamp_ptr) };
+        timestamp_fn(ticks, reload)
+    }
+}
+
+/// Get a timestamp with sub-tick resolution, from the given tick source
+///
+/// Arguments are as for [`timestamp`].
+fn timestamp_from<T>(ticks: u32, reload: u32) -> u32
+where
+    T: TickSource,
+{
+    let mut ticks = ticks;
+    let mut elapsed = T::counts_since_tick();
+    if T::tick_pending() {
+        // The timer has wrapped, but the interrupt handler hasn't counted the
+        // tick yet. Re-read the timer, because it may have wrapped after we
+        // first read it.
+        ticks = ticks.wrapping_add(1);
+        elapsed = T::counts_since_tick();
+    }
+    ticks
+        .wrapping_mul(reload.wrapping_add(1))
+        .wrapping_add(elapsed.min(reload))
+}
+
+/// Get the stack pointer for the running task
+///
+/// Tasks run on the Process Stack, so this is PSP.
+pub(crate) fn current_stack_pointer() -> Option<usize> {
+    Some(cortex_m::register::psp::read() as usize)
+}
+
+/// Called by the PendSV handler, between stacking the outgoing task and
+/// unstacking the incoming task.
+///
+/// This is a normal AAPCS function, so the PendSV handler must assume it
+/// trashes r0-r3, r12 and LR. It returns the ID of the task to switch to,
+/// which the PendSV handler must use instead of reading `next_task` again,
+/// because an interrupt could change `next_task` while we run.
+pub(crate) extern "C" fn task_switch_hook() -> usize {
+    let scheduler = Scheduler::get_scheduler().unwrap();
+    let next_task = scheduler.next_task_id();
+    scheduler.on_task_switch(next_task);
+    #[cfg(feature = "trustzone")]
+    crate::port::switch_secure_context(scheduler, next_task);
+    next_task.index()
+}
+
+/// Our SysTick Handler
+///
+/// Tells the global scheduler that maybe its time to think about changing
+/// which task is running.
+#[cfg(feature = "systick")]
+#[unsafe(no_mangle)]
+extern "C" fn SysTick() {
+    crate::on_tick();
+}
+
+// End of File
@@ -0,0 +1,394 @@
+//! The port for Arm Cortex-R, in AArch32 state
+//!
+//! Tasks run in System mode. We switch tasks in the IRQ and SVC handlers in
+//! [`crate::asm`], which save the interrupted task's registers on its stack
+//! and then call [`irq_handler`] or [`svc_handler`]. A task that wants to
+//! switch executes an `SVC` instruction; an interrupt handler that wants to
+//! switch just sets a flag, which is checked on the way out of the IRQ.
+//!
+//! On Armv8-R we use the Generic Timer's virtual timer to generate the
+//! scheduler tick, but you have to route it through your interrupt
+//! controller and call [`on_timer_interrupt`] from your `_irq_handler`.
+//! Armv7-R has no standard timer, so you call [`on_tick`](crate::on_tick)
+//! from your own timer interrupt instead.
+//!
+//! pets replaces the IRQ and SVC handlers from `cortex-r-rt`. Your
+//! `_irq_handler` is still called, but in IRQ mode rather than System mode,
+//! and it must not unmask IRQs. Your `_svc_handler` is not called.
+
+// Copyright (c) 2025 Ferrous Systems
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+#[cfg(arm_architecture = "v8-r")]
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{SchedPolicy, Scheduler, StackPusher};
+
+/// This is the minimum stack we can support, because of the state we need to push
+///
+/// Make space for sixteen 32-bit words of task state, plus some headroom
+#[cfg(arm_abi = "eabi")]
+pub(crate) const MIN_STACK_SIZE: usize = (4 * 16) + 8;
+
+/// This is the minimum stack we can support, because of the state we need to push
+///
+/// Make space for sixteen 32-bit words of task state, plus sixteen 64-bit FPU
+/// registers, plus FPSCR and a padding word, plus some headroom
+#[cfg(all(arm_abi = "eabihf", not(target_feature = "d32")))]
+pub(crate) const MIN_STACK_SIZE: usize = (4 * 16) + (8 * 16) + 8 + 8;
+
+/// This is the minimum stack we can support, because of the state we need to push
+///
+/// Make space for sixteen 32-bit words of task state, plus thirty-two 64-bit
+/// FPU registers, plus FPSCR and a padding word, plus some headroom
+#[cfg(all(arm_abi = "eabihf", target_feature = "d32"))]
+pub(crate) const MIN_STACK_SIZE: usize = (4 * 16) + (8 * 32) + 8 + 8;
+
+/// We paint task stacks before they start, so we can tell how much they used
+pub(crate) const PAINTS_STACKS: bool = true;
+
+/// The value of CPSR when a task starts
+///
+/// System mode, with IRQs and FIQs unmasked, in Arm state.
+const DEFAULT_CPSR: u32 = 0x1F;
+
+/// The Thumb bit in CPSR
+const CPSR_T: u32 = 1 << 5;
+
+/// The IRQ mask bit in CPSR
+const CPSR_I: u32 = 1 << 7;
+
+/// The mode bits in CPSR
+const CPSR_MODE_MASK: u32 = 0x1F;
+
+/// The mode bits for System mode, which tasks run in
+const CPSR_MODE_SYS: u32 = 0x1F;
+
+/// Set when we want to switch to the scheduler's `next_task`
+///
+/// We check (and clear) it on the way out of every IRQ and SVC.
+static SWITCH_PENDING: AtomicBool = AtomicBool::new(false);
+
+/// The number of timer counts in a scheduler tick
+#[cfg(arm_architecture = "v8-r")]
+static COUNTS_PER_SCHED_TICK: AtomicU32 = AtomicU32::new(0);
+
+unsafe extern "C" {
+    /// The application's IRQ handler, with the same name `cortex-r-rt` uses
+    fn _irq_handler();
+}
+
+/// Read CPSR
+fn cpsr() -> u32 {
+    let cpsr: u32;
+    // SAFETY: Reading CPSR has no side effects
+    unsafe {
+        core::arch::asm!("mrs {0}, cpsr", out(reg) cpsr, options(nomem, nostack));
+    }
+    cpsr
+}
+
+/// The virtual timer, from the Armv8-R Generic Timer
+///
+/// We use it rather than the EL1 physical timer because EL2 can stop EL1
+/// from using the physical timer.
+#[cfg(arm_architecture = "v8-r")]
+mod timer {
+    /// The enable bit in CNTV_CTL
+    pub(super) const CTL_ENABLE: u32 = 1 << 0;
+
+    /// The status bit in CNTV_CTL, which is set when the timer has fired
+    pub(super) const CTL_ISTATUS: u32 = 1 << 2;
+
+    /// Read the virtual count, CNTVCT
+    pub(super) fn count() -> u64 {
+        let (low, high): (u32, u32);
+        // SAFETY: Reading the count has no side effects
+        unsafe {
+            core::arch::asm!("mrrc p15, 1, {0}, {1}, c14", out(reg) low, out(reg) high, options(nomem, nostack));
+        }
+        (u64::from(high) << 32) | u64::from(low)
+    }
+
+    /// Read the compare value, CNTV_CVAL
+    pub(super) fn compare() -> u64 {
+        let (low, high): (u32, u32);
+        // SAFETY: Reading the compare value has no side effects
+        unsafe {
+            core::arch::asm!("mrrc p15, 3, {0}, {1}, c14", out(reg) low, out(reg) high, options(nomem, nostack));
+        }
+        (u64::from(high) << 32) | u64::from(low)
+    }
+
+    /// Write the compare value, CNTV_CVAL
+    pub(super) fn set_compare(value: u64) {
+        // SAFETY: Only the scheduler uses this timer
+        unsafe {
+            core::arch::asm!("mcrr p15, 3, {0}, {1}, c14", in(reg) value as u32, in(reg) (value >> 32) as u32, options(nomem, nostack));
+        }
+    }
+
+    /// Read the control register, CNTV_CTL
+    pub(super) fn control() -> u32 {
+        let value: u32;
+        // SAFETY: Reading the control register has no side effects
+        unsafe {
+            core::arch::asm!("mrc p15, 0, {0}, c14, c3, 1", out(reg) value, options(nomem, nostack));
+        }
+        value
+    }
+
+    /// Write the control register, CNTV_CTL
+    pub(super) fn set_control(value: u32) {
+        // SAFETY: Only the scheduler uses this timer
+        unsafe {
+            core::arch::asm!("mcr p15, 0, {0}, c14, c3, 1", in(reg) value, options(nomem, nostack));
+        }
+    }
+}
+
+impl<P: SchedPolicy + 'static> Scheduler<P> {
+    /// Run the scheduler
+    ///
+    /// You may only call this once, and you should call it from `fn main()`
+    /// once all your hardware is configured, including your interrupt
+    /// controller. We should be in System mode.
+    ///
+    /// On Armv8-R, the scheduler ticks every `counts_per_sched_tick` counts
+    /// of the Generic Timer, and your `_irq_handler` must call
+    /// [`on_timer_interrupt`](crate::cortex_r::on_timer_interrupt) when the
+    /// virtual timer fires. On Armv7-R, your `_irq_handler` must call
+    /// [`on_tick`](crate::on_tick) once every
+    /// `counts_per_sched_tick` counts of whatever timer you are using.
+    ///
+    /// Panics if `counts_per_sched_tick` is zero.
+    pub fn start(&self, counts_per_sched_tick: u32) -> ! {
+        assert!(
+            counts_per_sched_tick != 0,
+            "A tick can't be zero counts long"
+        );
+        // remember where this object is - it cannot move because we do not exit this function
+        self.register(counts_per_sched_tick - 1);
+
+        // We need to push some empty state into each task stack
+        for (task_idx, task) in self.task_list().iter().enumerate() {
+            let old_stack_top = task.stack();
+            info!(
+                "Init task frame {} ({}), with stack @ 0x{:08x}",
+                task_idx,
+                task.name().unwrap_or("unnamed"),
+                old_stack_top as usize
+            );
+
+            // SAFETY: The task hasn't started yet, so nothing is using its stack
+            unsafe {
+                task.paint_stack();
+            }
+
+            // SAFETY: The task constructor does not let us make tasks with
+            // stacks that are too small.
+            let mut stack_pusher = unsafe { StackPusher::new(old_stack_top) };
+
+            // Push the frame that `rfeia` pops
+            let entry_fn = task.entry_fn() as usize as u32;
+            let thumb = if (entry_fn & 1) != 0 { CPSR_T } else { 0 };
+            stack_pusher.push(DEFAULT_CPSR | thumb); // CPSR
+            stack_pusher.push(entry_fn & !1); // PC
+
+            // Push the frame that `pop {r0-r12, lr}` pops. Task entry
+            // functions never return, so LR is zero.
+            for _reg in 0..14 {
+                stack_pusher.push(0);
+            }
+
+            // Push the FPU state
+            #[cfg(arm_abi = "eabihf")]
+            {
+                #[cfg(target_feature = "d32")]
+                for _word in 0..32 {
+                    stack_pusher.push(0); // D16-D31
+                }
+                for _word in 0..32 {
+                    stack_pusher.push(0); // D0-D15
+                }
+                stack_pusher.push(0); // FPSCR
+                stack_pusher.push(0); // padding
+            }
+
+            debug!(
+                "Fini task frame {}, with stack @ 0x{:08x}",
+                task_idx,
+                stack_pusher.current() as usize
+            );
+
+            // SAFETY: the pointer we are passing is a validly aligned stack
+            // pointer, with a full frame above it
+            unsafe {
+                task.set_stack(stack_pusher.current());
+            }
+        }
+
+        // Start the tick
+        #[cfg(arm_architecture = "v8-r")]
+        {
+            COUNTS_PER_SCHED_TICK.store(counts_per_sched_tick, Ordering::Relaxed);
+            timer::set_compare(timer::count() + u64::from(counts_per_sched_tick));
+            timer::set_control(timer::CTL_ENABLE);
+        }
+
+        // Take an SVC - the handler will select a task to run and run it.
+        // This function's state is discarded.
+        debug!("Hit SVC");
+        SWITCH_PENDING.store(true, Ordering::Release);
+        // SAFETY: We have set up the task stacks
+        unsafe {
+            core::arch::asm!("svc #0");
+        }
+        loop {
+            // impossible to stay here
+            wait_for_interrupt();
+        }
+    }
+}
+
+/// Call this from your `_irq_handler` when the virtual timer fires
+///
+/// It re-arms the timer and ticks the scheduler. Any task switch happens
+/// once your `_irq_handler` returns.
+#[cfg(arm_architecture = "v8-r")]
+pub fn on_timer_interrupt() {
+    let period = COUNTS_PER_SCHED_TICK.load(Ordering::Relaxed);
+    timer::set_compare(timer::compare() + u64::from(period));
+    crate::on_tick();
+}
+
+/// Run a closure with interrupts disabled
+pub(crate) fn critical_section<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let cpsr = cpsr();
+    // SAFETY: Masking IRQs has no other side effects
+    unsafe {
+        core::arch::asm!("cpsid i", options(nomem, nostack));
+    }
+    let result = f();
+    if (cpsr & CPSR_I) == 0 {
+        // SAFETY: IRQs were enabled before, so we can enable them again
+        unsafe {
+            core::arch::asm!("cpsie i", options(nomem, nostack));
+        }
+    }
+    result
+}
+
+/// Ask for a switch to the scheduler's `next_task`
+///
+/// In an interrupt handler, the switch happens on the way out of the IRQ.
+/// In a task, we take an SVC to switch straight away.
+pub(crate) fn request_task_switch<P: SchedPolicy + ?Sized>(_scheduler: &Scheduler<P>) {
+    SWITCH_PENDING.store(true, Ordering::Release);
+    if (cpsr() & CPSR_MODE_MASK) == CPSR_MODE_SYS {
+        // SAFETY: The SVC handler saves and restores everything
+        unsafe {
+            core::arch::asm!("svc #0");
+        }
+    }
+}
+
+/// Sleep until an interrupt occurs
+pub(crate) fn wait_for_interrupt() {
+    // SAFETY: Waiting for an interrupt has no side effects
+    unsafe {
+        core::arch::asm!("wfi", options(nomem, nostack));
+    }
+}
+
+/// Get a timestamp with sub-tick resolution, measured in Generic Timer counts
+///
+/// Give it the current tick count, and one less than the number of counts in
+/// a tick. Only call this from within a critical section.
+#[cfg(arm_architecture = "v8-r")]
+pub(crate) fn timestamp(ticks: u32, reload: u32) -> u32 {
+    let period = reload.wrapping_add(1);
+    let mut ticks = ticks;
+    // How far we are through the current tick
+    let mut elapsed = (timer::count() as u32)
+        .wrapping_add(period)
+        .wrapping_sub(timer::compare() as u32);
+    if (timer::control() & timer::CTL_ISTATUS) != 0 {
+        // The timer has fired, but the IRQ handler hasn't counted the tick
+        ticks = ticks.wrapping_add(1);
+        elapsed = elapsed.wrapping_sub(period);
+    }
+    ticks.wrapping_mul(period).wrapping_add(elapsed.min(reload))
+}
+
+/// Get a timestamp, measured in timer counts
+///
+/// We don't know which timer you are using, so this only has tick
+/// resolution.
+#[cfg(not(arm_architecture = "v8-r"))]
+pub(crate) fn timestamp(ticks: u32, reload: u32) -> u32 {
+    ticks.wrapping_mul(reload.wrapping_add(1))
+}
+
+/// Are we in an exception handler, rather than a task?
+///
+/// Tasks run in System mode, and nothing else does.
+pub(crate) fn in_interrupt() -> bool {
+    (cpsr() & CPSR_MODE_MASK) != CPSR_MODE_SYS
+}
+
+/// Get the stack pointer for the running task
+///
+/// Tasks run on their own stacks, in System mode, so this is just `sp`.
+pub(crate) fn current_stack_pointer() -> Option<usize> {
+    let sp: usize;
+    // SAFETY: Reading the stack pointer has no side effects
+    unsafe {
+        core::arch::asm!("mov {0}, sp", out(reg) sp, options(nomem, nostack));
+    }
+    Some(sp)
+}
+
+/// Called by our IRQ handler, once it has stacked the interrupted task
+///
+/// We are given the interrupted task's stack pointer, and return the stack
+/// pointer of the task to resume, which is different if we switched tasks.
+pub(crate) extern "C" fn irq_handler(stack: *mut u32) -> *mut u32 {
+    // SAFETY: `cortex-r-rt` requires the application to supply this function
+    unsafe {
+        _irq_handler();
+    }
+    switch_if_pending(stack)
+}
+
+/// Called by our SVC handler, once it has stacked the calling task
+///
+/// We are given the calling task's stack pointer, and return the stack
+/// pointer of the task to resume, which is different if we switched tasks.
+pub(crate) extern "C" fn svc_handler(stack: *mut u32) -> *mut u32 {
+    switch_if_pending(stack)
+}
+
+/// Switch to the scheduler's `next_task`, if someone asked us to
+fn switch_if_pending(stack: *mut u32) -> *mut u32 {
+    if !SWITCH_PENDING.swap(false, Ordering::Acquire) {
+        return stack;
+    }
+    let scheduler = Scheduler::get_scheduler().unwrap();
+    let task_list = scheduler.task_list();
+    if let Some(task) = task_list.get(scheduler.current_task_id().index()) {
+        // SAFETY: The IRQ or SVC handler gave us the task's stack pointer,
+        // with a full frame above it
+        unsafe {
+            task.set_stack(stack);
+        }
+    }
+    scheduler.switch_task();
+    task_list[scheduler.current_task_id().index()].stack()
+}
+
+// End of File
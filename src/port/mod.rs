@@ -0,0 +1,80 @@
+//! The glue between the scheduler and the hardware it runs on
+//!
+//! Everything in [`Scheduler`](crate::Scheduler) is hardware-independent. A
+//! port supplies the rest:
+//!
+//! * `MIN_STACK_SIZE` - the smallest task stack we can cope with
+//! * `PAINTS_STACKS` - do we paint task stacks, so we can measure their use?
+//! * `critical_section` - run a closure with the scheduler's interrupts masked
+//! * `request_task_switch` - switch to `next_task` as soon as possible
+//! * `wait_for_interrupt` - sleep until something happens
+//! * `timestamp` - the current time, with better than tick resolution
+//! * `current_stack_pointer` - the stack pointer of the running task
+//! * `in_interrupt` - are we in an interrupt handler, rather than a task?
+//!
+//! It also has to start the scheduler, and make sure
+//! [`on_tick`](crate::on_tick) is called periodically.
+
+// Copyright (c) 2025 Ferrous Systems
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+#[cfg(pets_port = "cortex-m")]
+mod cortex_m;
+
+#[cfg(pets_port = "cortex-m")]
+pub(crate) use self::cortex_m::*;
+
+#[cfg(pets_port = "cortex-m")]
+pub use self::cortex_m::TickSource;
+
+#[cfg(pets_port = "cortex-m")]
+pub use self::cortex_m::set_kernel_priority;
+#[cfg(pets_basepri)]
+pub use self::cortex_m::set_max_syscall_priority;
+
+#[cfg(all(pets_port = "cortex-m", feature = "trustzone"))]
+mod trustzone;
+
+#[cfg(all(pets_port = "cortex-m", feature = "trustzone"))]
+pub(crate) use self::trustzone::switch_secure_context;
+
+#[cfg(all(pets_port = "cortex-m", feature = "trustzone"))]
+pub use self::trustzone::{
+    SecureContextApi, SecureContextError, allocate_secure_context, set_secure_context_api,
+};
+
+#[cfg(all(
+    pets_port = "cortex-m",
+    feature = "trustzone",
+    not(any(arm_architecture = "v8-m.base", arm_architecture = "v8-m.main"))
+))]
+compile_error!("The `trustzone` feature needs an Armv8-M CPU");
+
+#[cfg(pets_port = "cortex-r")]
+mod cortex_r;
+
+#[cfg(pets_port = "cortex-r")]
+pub(crate) use self::cortex_r::*;
+
+#[cfg(all(pets_port = "cortex-r", arm_architecture = "v8-r"))]
+pub use self::cortex_r::on_timer_interrupt;
+
+#[cfg(pets_port = "riscv")]
+mod riscv;
+
+#[cfg(pets_port = "riscv")]
+pub(crate) use self::riscv::*;
+
+#[cfg(pets_port = "riscv")]
+pub use self::riscv::Clint;
+
+#[cfg(pets_port = "sim")]
+mod sim;
+
+#[cfg(pets_port = "sim")]
+pub(crate) use sim::*;
+
+#[cfg(pets_port = "sim")]
+pub use sim::{Run, Sim, spin};
+
+// End of File
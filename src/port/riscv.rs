@@ -0,0 +1,389 @@
+//! The port for 32-bit RISC-V, in machine mode
+//!
+//! We use the CLINT's machine timer to generate the scheduler tick, and the
+//! CLINT's machine software interrupt to switch tasks, just like PendSV on
+//! Cortex-M. Every trap goes through the vector in [`crate::asm`], which
+//! saves the interrupted task's registers on its stack and then calls
+//! [`trap_handler`].
+//!
+//! pets takes over `mtvec`, so your application cannot have interrupt
+//! handlers of its own yet.
+
+// Copyright (c) 2025 Ferrous Systems
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use crate::{SchedPolicy, Scheduler, Stack, StackPusher};
+
+/// This is the minimum stack we can support, because of the state we need to push
+///
+/// Make space for thirty-two 32-bit words of task state, plus some headroom
+pub(crate) const MIN_STACK_SIZE: usize = (4 * 32) + 16;
+
+/// We paint task stacks before they start, so we can tell how much they used
+pub(crate) const PAINTS_STACKS: bool = true;
+
+/// How many words of task state we push onto a task's stack
+///
+/// Word N holds register xN, except that word 0 holds `mepc` and word 2
+/// holds `mstatus`. We don't save `sp` (it's the stack pointer we store in
+/// the task), or `gp` and `tp` (which are the same for every task).
+pub(crate) const FRAME_WORDS: usize = 32;
+
+/// The value of `mstatus` when a task starts
+///
+/// MPP = Machine, so we stay in machine mode, and MPIE = 1, so interrupts
+/// are enabled once the task is running.
+const DEFAULT_MSTATUS: u32 = (0b11 << 11) | (1 << 7);
+
+/// The size of the stack we use when handling traps
+pub(crate) const TRAP_STACK_SIZE: usize = 1024;
+
+/// The stack we use when handling traps
+///
+/// Traps don't nest, so one is enough.
+pub(crate) static TRAP_STACK: Stack<TRAP_STACK_SIZE> = Stack::new();
+
+/// The base address of the CLINT, once the scheduler has started
+static CLINT_BASE: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of `mtime` counts in a scheduler tick
+static MTIME_PER_SCHED_TICK: AtomicU32 = AtomicU32::new(0);
+
+/// The bit in `mstatus`, `mie` and `mip` for machine-mode interrupts
+const MSTATUS_MIE: usize = 1 << 3;
+
+/// The bit in `mie` and `mip` for the machine software interrupt
+const MIE_MSIE: usize = 1 << 3;
+
+/// The bit in `mie` and `mip` for the machine timer interrupt
+const MIE_MTIE: usize = 1 << 7;
+
+/// The `mcause` value for a machine software interrupt
+const CAUSE_MACHINE_SOFT: usize = (1 << 31) | 3;
+
+/// The `mcause` value for a machine timer interrupt
+const CAUSE_MACHINE_TIMER: usize = (1 << 31) | 7;
+
+unsafe extern "C" {
+    /// Our trap vector, which is in [`crate::asm`]
+    fn pets_trap();
+}
+
+/// Read a CSR
+macro_rules! read_csr {
+    ($csr:literal) => {{
+        let value: usize;
+        // SAFETY: Reading a CSR has no side effects
+        unsafe {
+            core::arch::asm!(concat!("csrr {0}, ", $csr), out(reg) value, options(nomem, nostack));
+        }
+        value
+    }};
+}
+
+/// The Core-Local Interruptor, which has the machine timer and software interrupts
+///
+/// pets only uses the registers for hart 0.
+pub struct Clint {
+    /// The base address of the CLINT's registers
+    base: usize,
+}
+
+impl Clint {
+    /// The base address of the CLINT on QEMU's `virt` and `sifive_e` machines
+    pub const QEMU_BASE: usize = 0x0200_0000;
+
+    /// The offset to `msip` for hart 0
+    const MSIP_OFFSET: usize = 0x0000;
+
+    /// The offset to `mtimecmp` for hart 0
+    const MTIMECMP_OFFSET: usize = 0x4000;
+
+    /// The offset to `mtime`
+    const MTIME_OFFSET: usize = 0xBFF8;
+
+    /// Create a handle to the CLINT at the given address
+    ///
+    /// # Safety
+    ///
+    /// There must be a CLINT at `base`, and nothing else may use it once you
+    /// give it to the scheduler.
+    pub const unsafe fn new(base: usize) -> Clint {
+        Clint { base }
+    }
+
+    /// Get the CLINT the scheduler is using
+    fn get() -> Clint {
+        Clint {
+            base: CLINT_BASE.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Get a pointer to one of the CLINT's registers
+    fn register(&self, offset: usize) -> *mut u32 {
+        (self.base + offset) as *mut u32
+    }
+
+    /// Set or clear the machine software interrupt for hart 0
+    fn set_msip(&self, pending: bool) {
+        // SAFETY: The creator of the Clint promised it was at this address
+        unsafe {
+            self.register(Self::MSIP_OFFSET)
+                .write_volatile(u32::from(pending));
+        }
+    }
+
+    /// Read the bottom 32 bits of `mtime`
+    fn mtime(&self) -> u32 {
+        // SAFETY: The creator of the Clint promised it was at this address
+        unsafe { self.register(Self::MTIME_OFFSET).read_volatile() }
+    }
+
+    /// Read `mtimecmp` for hart 0
+    fn mtimecmp(&self) -> u64 {
+        // SAFETY: The creator of the Clint promised it was at this address,
+        // and only we write to `mtimecmp`, so it can't change between reads
+        unsafe {
+            let low = self.register(Self::MTIMECMP_OFFSET).read_volatile();
+            let high = self.register(Self::MTIMECMP_OFFSET + 4).read_volatile();
+            (u64::from(high) << 32) | u64::from(low)
+        }
+    }
+
+    /// Write `mtimecmp` for hart 0
+    fn set_mtimecmp(&self, value: u64) {
+        // SAFETY: The creator of the Clint promised it was at this address.
+        // We set the top half to its maximum first, so that we don't get a
+        // spurious interrupt whilst the two halves don't match.
+        unsafe {
+            let low = self.register(Self::MTIMECMP_OFFSET);
+            let high = self.register(Self::MTIMECMP_OFFSET + 4);
+            high.write_volatile(u32::MAX);
+            low.write_volatile(value as u32);
+            high.write_volatile((value >> 32) as u32);
+        }
+    }
+
+    /// Read the whole of `mtime`
+    fn mtime64(&self) -> u64 {
+        // SAFETY: The creator of the Clint promised it was at this address
+        unsafe {
+            loop {
+                let high = self.register(Self::MTIME_OFFSET + 4).read_volatile();
+                let low = self.register(Self::MTIME_OFFSET).read_volatile();
+                if high == self.register(Self::MTIME_OFFSET + 4).read_volatile() {
+                    return (u64::from(high) << 32) | u64::from(low);
+                }
+            }
+        }
+    }
+}
+
+impl<P: SchedPolicy + 'static> Scheduler<P> {
+    /// Run the scheduler
+    ///
+    /// You may only call this once, and you should call it from `fn main()`
+    /// once all your hardware is configured. We should be in machine mode.
+    /// The scheduler ticks every `mtime_per_sched_tick` counts of the CLINT's
+    /// `mtime` register.
+    ///
+    /// Panics if `mtime_per_sched_tick` is zero.
+    pub fn start(&self, clint: Clint, mtime_per_sched_tick: u32) -> ! {
+        assert!(
+            mtime_per_sched_tick != 0,
+            "A tick can't be zero counts long"
+        );
+        // remember where this object is - it cannot move because we do not exit this function
+        self.register(mtime_per_sched_tick - 1);
+        CLINT_BASE.store(clint.base, Ordering::Relaxed);
+        MTIME_PER_SCHED_TICK.store(mtime_per_sched_tick, Ordering::Relaxed);
+
+        // We need to push some empty state into each task stack
+        for (task_idx, task) in self.task_list().iter().enumerate() {
+            let old_stack_top = task.stack();
+            info!(
+                "Init task frame {} ({}), with stack @ 0x{:08x}",
+                task_idx,
+                task.name().unwrap_or("unnamed"),
+                old_stack_top as usize
+            );
+
+            // SAFETY: The task hasn't started yet, so nothing is using its stack
+            unsafe {
+                task.paint_stack();
+            }
+
+            // SAFETY: The task constructor does not let us make tasks with
+            // stacks that are too small.
+            let mut stack_pusher = unsafe { StackPusher::new(old_stack_top) };
+
+            // Push the frame top-down, so word 0 ends up at the lowest
+            // address. Every register starts as zero, including `ra`,
+            // because task entry functions never return.
+            for word in (0..FRAME_WORDS).rev() {
+                stack_pusher.push(match word {
+                    0 => task.entry_fn() as usize as u32,
+                    2 => DEFAULT_MSTATUS,
+                    _ => 0,
+                });
+            }
+
+            debug!(
+                "Fini task frame {}, with stack @ 0x{:08x}",
+                task_idx,
+                stack_pusher.current() as usize
+            );
+
+            // SAFETY: the pointer we are passing is a validly aligned stack
+            // pointer, with a full frame above it
+            unsafe {
+                task.set_stack(stack_pusher.current());
+            }
+        }
+
+        // SAFETY: Our trap vector is 4-byte aligned, so this selects Direct
+        // mode. We are the only code using interrupts.
+        unsafe {
+            core::arch::asm!("csrw mtvec, {0}", in(reg) pets_trap as *const () as usize);
+        }
+
+        // Start the tick
+        clint.set_mtimecmp(clint.mtime64() + u64::from(mtime_per_sched_tick));
+
+        // Fire the machine software interrupt - the trap handler will select
+        // a task to run and run it. This function's state is discarded.
+        debug!("Hit MSIP");
+        clint.set_msip(true);
+        // SAFETY: We have set up the trap vector and the task stacks
+        unsafe {
+            core::arch::asm!(
+                "csrs mie, {0}",
+                "csrs mstatus, {1}",
+                in(reg) MIE_MSIE | MIE_MTIE,
+                in(reg) MSTATUS_MIE,
+            );
+        }
+        loop {
+            // impossible to stay here
+            wait_for_interrupt();
+        }
+    }
+}
+
+/// Run a closure with interrupts disabled
+pub(crate) fn critical_section<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let mstatus: usize;
+    // SAFETY: Clearing MIE only masks interrupts
+    unsafe {
+        core::arch::asm!("csrrc {0}, mstatus, {1}", out(reg) mstatus, in(reg) MSTATUS_MIE);
+    }
+    let result = f();
+    if (mstatus & MSTATUS_MIE) != 0 {
+        // SAFETY: Interrupts were enabled before, so we can enable them again
+        unsafe {
+            core::arch::asm!("csrs mstatus, {0}", in(reg) MSTATUS_MIE);
+        }
+    }
+    result
+}
+
+/// Ask for a switch to the scheduler's `next_task`
+///
+/// The switch happens in the machine software interrupt, which is taken
+/// once we leave any trap handler we are in.
+pub(crate) fn request_task_switch<P: SchedPolicy + ?Sized>(_scheduler: &Scheduler<P>) {
+    Clint::get().set_msip(true);
+}
+
+/// Sleep until an interrupt occurs
+pub(crate) fn wait_for_interrupt() {
+    // SAFETY: Waiting for an interrupt has no side effects
+    unsafe {
+        core::arch::asm!("wfi", options(nomem, nostack));
+    }
+}
+
+/// Get a timestamp with sub-tick resolution, measured in `mtime` counts
+///
+/// Give it the current tick count, and one less than the number of `mtime`
+/// counts in a tick. Only call this from within a critical section.
+pub(crate) fn timestamp(ticks: u32, reload: u32) -> u32 {
+    let clint = Clint::get();
+    let period = reload.wrapping_add(1);
+    let mut ticks = ticks;
+    // How far we are through the current tick
+    let mut elapsed = clint
+        .mtime()
+        .wrapping_add(period)
+        .wrapping_sub(clint.mtimecmp() as u32);
+    if (read_csr!("mip") & MIE_MTIE) != 0 {
+        // The timer has fired, but the trap handler hasn't counted the tick
+        ticks = ticks.wrapping_add(1);
+        elapsed = elapsed.wrapping_sub(period);
+    }
+    ticks.wrapping_mul(period).wrapping_add(elapsed.min(reload))
+}
+
+/// Are we in a trap handler, rather than a task?
+///
+/// Trap handlers run on [`TRAP_STACK`], so we just look at `sp`.
+pub(crate) fn in_interrupt() -> bool {
+    let sp = current_stack_pointer().unwrap_or(0);
+    (TRAP_STACK.bottom() as usize..TRAP_STACK.top() as usize).contains(&sp)
+}
+
+/// Get the stack pointer for the running task
+///
+/// Tasks run on their own stacks, so this is just `sp`.
+pub(crate) fn current_stack_pointer() -> Option<usize> {
+    let sp: usize;
+    // SAFETY: Reading the stack pointer has no side effects
+    unsafe {
+        core::arch::asm!("mv {0}, sp", out(reg) sp, options(nomem, nostack));
+    }
+    Some(sp)
+}
+
+/// Called by our trap vector, once it has stacked the interrupted task
+///
+/// We are given the interrupted task's stack pointer, and return the stack
+/// pointer of the task to resume, which is different if we switched tasks.
+pub(crate) extern "C" fn trap_handler(stack: *mut u32) -> *mut u32 {
+    let scheduler = Scheduler::get_scheduler().unwrap();
+    match read_csr!("mcause") {
+        CAUSE_MACHINE_SOFT => {
+            Clint::get().set_msip(false);
+            let task_list = scheduler.task_list();
+            if let Some(task) = task_list.get(scheduler.current_task_id().index()) {
+                // SAFETY: The trap vector gave us the task's stack pointer,
+                // with a full frame above it
+                unsafe {
+                    task.set_stack(stack);
+                }
+            }
+            scheduler.switch_task();
+            task_list[scheduler.current_task_id().index()].stack()
+        }
+        CAUSE_MACHINE_TIMER => {
+            let clint = Clint::get();
+            let period = MTIME_PER_SCHED_TICK.load(Ordering::Relaxed);
+            clint.set_mtimecmp(clint.mtimecmp() + u64::from(period));
+            scheduler.sched_tick();
+            stack
+        }
+        mcause => {
+            panic!(
+                "Unexpected trap, mcause=0x{:08x} mepc=0x{:08x}",
+                mcause,
+                read_csr!("mepc")
+            );
+        }
+    }
+}
+
+// End of File
@@ -0,0 +1,447 @@
+//! A port for running pets tasks as threads on your computer
+//!
+//! Each [`Task`](crate::Task) gets its own OS thread, but only one thread
+//! ever runs at a time - just like only one task ever runs at a time on a
+//! real CPU. The clock is virtual, and only moves when you tell it to, using
+//! a [`Sim`]. This lets you test task code on any machine that has `std`.
+//!
+//! The [`Sim`] records every task switch, so tests can check which task ran
+//! when, and it can run closures as if they were interrupt handlers.
+//!
+//! We cannot interrupt an OS thread, so task switches only happen when a task
+//! calls into pets (e.g. [`crate::delay`]). A task which spins forever without
+//! calling into pets will hang the simulation. To pretend a task is busy for
+//! a while, have it call [`spin`].
+//!
+//! Without a [`Sim`] (e.g. in the scheduler's own unit tests), task switches
+//! happen immediately and waiting for an interrupt returns straight away.
+
+// Copyright (c) 2025 Ferrous Systems
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    any::Any,
+    cell::RefCell,
+    marker::PhantomData,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use crate::{SchedPolicy, Scheduler, TaskId};
+
+/// We never push anything onto task stacks, so any size will do
+pub(crate) const MIN_STACK_SIZE: usize = 0;
+
+/// Tasks run on their thread's stack, not their [`Stack`](crate::Stack), so
+/// we can't tell how much stack they used
+pub(crate) const PAINTS_STACKS: bool = false;
+
+/// How many timer counts make up one tick in a simulation
+///
+/// This sets the resolution of the timestamps given to trace hooks.
+const COUNTS_PER_TICK: u32 = 1000;
+
+/// Who is allowed to run right now
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Holder {
+    /// The [`Sim`] - the tasks are all waiting for a tick, or an interrupt
+    Harness,
+    /// The task with this index
+    Task(usize),
+}
+
+/// A task being switched in, as recorded by a [`Sim`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Run {
+    /// The tick count when the task was switched in
+    pub tick: u32,
+    /// The task that was switched in
+    pub task: TaskId,
+}
+
+/// The state of our simulated CPU
+struct CpuState {
+    /// Who is allowed to run right now
+    holder: Holder,
+    /// Set when the [`Sim`] is dropped, to tell the tasks to exit
+    shutdown: bool,
+    /// A panic from a task thread, which the harness should re-throw
+    panic: Option<Box<dyn Any + Send>>,
+    /// Every task switch so far, oldest first
+    history: Vec<Run>,
+}
+
+/// A simulated CPU, which can only run one thread at a time
+struct Cpu {
+    /// The scheduler whose tasks we are running
+    scheduler: &'static Scheduler<dyn SchedPolicy>,
+    /// The state of the CPU
+    state: Mutex<CpuState>,
+    /// Signalled whenever the state changes
+    changed: Condvar,
+}
+
+/// Thrown inside a task thread to make it exit when the [`Sim`] is dropped
+struct Shutdown;
+
+impl Cpu {
+    /// Lock the CPU state
+    fn lock(&self) -> MutexGuard<'_, CpuState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Give the CPU to `to`, and wait until `me` gets it back
+    fn switch(&self, to: Holder, me: Holder) {
+        let mut state = self.lock();
+        state.holder = to;
+        self.changed.notify_all();
+        while state.holder != me {
+            if state.shutdown && me != Holder::Harness {
+                drop(state);
+                std::panic::resume_unwind(Box::new(Shutdown));
+            }
+            state = self.changed.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Wait until `me` gets the CPU
+    fn wait_for(&self, me: Holder) {
+        let mut state = self.lock();
+        while state.holder != me {
+            if state.shutdown {
+                drop(state);
+                std::panic::resume_unwind(Box::new(Shutdown));
+            }
+            state = self.changed.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// A task panicked - give the CPU back to the harness, along with the panic
+    fn task_panicked(&self, payload: Box<dyn Any + Send>) {
+        let mut state = self.lock();
+        state.panic = Some(payload);
+        state.holder = Holder::Harness;
+        self.changed.notify_all();
+    }
+
+    /// Switch to the scheduler's `next_task`, and record that we did so
+    fn switch_task(&self) {
+        self.scheduler.switch_task();
+        let run = Run {
+            tick: self.scheduler.now(),
+            task: self.scheduler.current_task_id(),
+        };
+        self.lock().history.push(run);
+    }
+}
+
+/// What the current thread is doing in a simulation
+struct Context {
+    /// The simulated CPU this thread belongs to
+    cpu: Arc<Cpu>,
+    /// Who this thread is
+    role: Holder,
+}
+
+std::thread_local! {
+    /// What the current thread is doing in a simulation, if anything
+    static CONTEXT: RefCell<Option<Context>> = const { RefCell::new(None) };
+}
+
+/// Get the simulated CPU, and our role on it, for the current thread
+fn context() -> Option<(Arc<Cpu>, Holder)> {
+    CONTEXT.with_borrow(|context| {
+        context
+            .as_ref()
+            .map(|context| (context.cpu.clone(), context.role))
+    })
+}
+
+/// Runs a [`Scheduler`] and its tasks on your computer, with a virtual clock
+///
+/// ```rust
+/// use pets::{Scheduler, Stack, Task, sim::Sim};
+///
+/// static STACK: Stack<64> = Stack::new();
+/// static TASK_LIST: [Task; 1] = [Task::new(blinky, &STACK)];
+/// static SCHEDULER: Scheduler = Scheduler::new(&TASK_LIST);
+///
+/// fn blinky() -> ! {
+///     loop {
+///         pets::delay(5);
+///     }
+/// }
+///
+/// let sim = Sim::new(&SCHEDULER);
+/// sim.advance(100);
+/// assert_eq!(pets::now(), 100);
+/// ```
+///
+/// Each `Sim` needs its own `Scheduler` and its own tasks. Whilst the `Sim`
+/// exists, the thread that created it can use functions like [`crate::now`]
+/// to look at the simulation.
+pub struct Sim {
+    /// The simulated CPU
+    cpu: Arc<Cpu>,
+    /// One thread per task
+    threads: Vec<JoinHandle<()>>,
+    /// We use thread-locals, so we must stay on the thread that made us
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Sim {
+    /// Start the given scheduler in a simulation
+    ///
+    /// The first task runs until it gives up the CPU, as do any tasks it
+    /// switches to, and then the time is zero ticks.
+    ///
+    /// Panics if the scheduler has already been started, or if this thread
+    /// is already running a simulation.
+    pub fn new<P: SchedPolicy>(scheduler: &'static Scheduler<P>) -> Sim {
+        if context().is_some() {
+            panic!("This thread is already running a simulation");
+        }
+        scheduler.prepare_start(COUNTS_PER_TICK - 1);
+        let cpu = Arc::new(Cpu {
+            scheduler,
+            state: Mutex::new(CpuState {
+                holder: Holder::Harness,
+                shutdown: false,
+                panic: None,
+                history: Vec::new(),
+            }),
+            changed: Condvar::new(),
+        });
+
+        let threads = scheduler
+            .task_list()
+            .iter()
+            .enumerate()
+            .map(|(task_idx, task)| {
+                let cpu = cpu.clone();
+                let entry_fn = task.entry_fn();
+                std::thread::Builder::new()
+                    .name(format!("pets-task-{}", task_idx))
+                    .spawn(move || Self::task_thread(cpu, task_idx, entry_fn))
+                    .expect("spawning task thread")
+            })
+            .collect();
+
+        CONTEXT.set(Some(Context {
+            cpu: cpu.clone(),
+            role: Holder::Harness,
+        }));
+
+        let sim = Sim {
+            cpu,
+            threads,
+            _not_send: PhantomData,
+        };
+
+        // Do what PendSV does when the scheduler starts
+        sim.cpu.switch_task();
+        sim.dispatch();
+        sim
+    }
+
+    /// The body of each task thread
+    fn task_thread(cpu: Arc<Cpu>, task_idx: usize, entry_fn: crate::task::TaskEntryFn) {
+        let me = Holder::Task(task_idx);
+        CONTEXT.set(Some(Context {
+            cpu: cpu.clone(),
+            role: me,
+        }));
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            cpu.wait_for(me);
+            entry_fn();
+        }));
+        if let Err(payload) = result
+            && !payload.is::<Shutdown>()
+        {
+            cpu.task_panicked(payload);
+        }
+    }
+
+    /// Let the current task run, until every task is waiting for an interrupt
+    ///
+    /// If a task panics, the panic is passed on to the caller.
+    fn dispatch(&self) {
+        let current_task = self.cpu.scheduler.current_task_id();
+        if current_task.is_invalid() {
+            return;
+        }
+        self.cpu
+            .switch(Holder::Task(current_task.index()), Holder::Harness);
+        if let Some(payload) = self.cpu.lock().panic.take() {
+            std::panic::resume_unwind(payload);
+        }
+    }
+
+    /// Deliver one scheduler tick, and run tasks until they are all waiting
+    pub fn tick(&self) {
+        self.cpu.scheduler.sched_tick();
+        self.dispatch();
+    }
+
+    /// Deliver the given number of scheduler ticks
+    pub fn advance(&self, ticks: u32) {
+        for _ in 0..ticks {
+            self.tick();
+        }
+    }
+
+    /// Deliver the given number of scheduler ticks, one every `period`
+    ///
+    /// Use this if your tasks talk to something outside the simulation which
+    /// expects time to pass.
+    pub fn advance_realtime(&self, ticks: u32, period: Duration) {
+        for _ in 0..ticks {
+            std::thread::sleep(period);
+            self.tick();
+        }
+    }
+
+    /// Run a closure as if it were an interrupt handler
+    ///
+    /// The closure runs whilst every task is waiting, just like a real
+    /// interrupt would. Afterwards, the task that was waiting for an
+    /// interrupt is woken up, and tasks run until they are all waiting again.
+    /// No time passes.
+    pub fn interrupt<F, R>(&self, handler: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        let result = handler();
+        self.dispatch();
+        result
+    }
+
+    /// Get the current time, in ticks
+    pub fn now(&self) -> u32 {
+        self.cpu.scheduler.now()
+    }
+
+    /// Get the ID of the task at the given position in the task list
+    ///
+    /// Panics if there is no such task.
+    pub fn task_id(&self, index: usize) -> TaskId {
+        assert!(
+            index < self.cpu.scheduler.task_list().len(),
+            "No task with index {}",
+            index
+        );
+        TaskId::new(index)
+    }
+
+    /// Get every task switch so far, oldest first
+    pub fn history(&self) -> Vec<Run> {
+        self.cpu.lock().history.clone()
+    }
+
+    /// Get every task switch since this was last called, oldest first
+    ///
+    /// Useful for checking what happened in each step of a test.
+    pub fn take_history(&self) -> Vec<Run> {
+        core::mem::take(&mut self.cpu.lock().history)
+    }
+}
+
+impl Drop for Sim {
+    fn drop(&mut self) {
+        {
+            let mut state = self.cpu.lock();
+            state.shutdown = true;
+            self.cpu.changed.notify_all();
+        }
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+        CONTEXT.set(None);
+    }
+}
+
+/// Keep the calling task busy until `ticks` ticks have passed
+///
+/// This is what a task computing something, without calling into pets, looks
+/// like to the scheduler. The ticks still arrive (when the [`Sim`] delivers
+/// them), and the scheduler can switch to other tasks when they do. The time
+/// is charged to this task, not to idle.
+///
+/// Panics if not called from a task in a simulation.
+pub fn spin(ticks: u32) {
+    let Some((cpu, me @ Holder::Task(_))) = context() else {
+        panic!("Only a task in a simulation can spin");
+    };
+    let until = cpu.scheduler.now().wrapping_add(ticks);
+    // Until then, each time the harness gets the CPU back, it delivers a tick
+    while (until.wrapping_sub(cpu.scheduler.now()) as i32) > 0 {
+        cpu.switch(Holder::Harness, me);
+    }
+}
+
+/// Run a closure in a critical section
+///
+/// Only one thread in a simulation ever runs at a time, so this just runs
+/// the closure.
+pub(crate) fn critical_section<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    f()
+}
+
+/// Switch to the scheduler's `next_task`
+///
+/// If a task asked for the switch, it waits here until it is switched back
+/// in. If the [`Sim`] asked (i.e. from a tick), the new task runs when the
+/// [`Sim`] dispatches it.
+pub(crate) fn request_task_switch<P: SchedPolicy + ?Sized>(scheduler: &Scheduler<P>) {
+    match context() {
+        Some((cpu, me @ Holder::Task(_))) => {
+            cpu.switch_task();
+            let next = Holder::Task(scheduler.current_task_id().index());
+            cpu.switch(next, me);
+        }
+        Some((cpu, Holder::Harness)) => cpu.switch_task(),
+        None => scheduler.switch_task(),
+    }
+}
+
+/// Wait for an interrupt
+///
+/// Gives the CPU back to the [`Sim`] until this task is switched back in.
+pub(crate) fn wait_for_interrupt() {
+    if let Some((cpu, me @ Holder::Task(_))) = context() {
+        cpu.switch(Holder::Harness, me);
+    }
+}
+
+/// Get a timestamp, in timer counts
+///
+/// The virtual clock only moves in whole ticks, of `reload + 1` counts.
+pub(crate) fn timestamp(ticks: u32, reload: u32) -> u32 {
+    ticks.wrapping_mul(reload.wrapping_add(1))
+}
+
+/// Are we in an interrupt handler, rather than a task?
+///
+/// In a simulation, the [`Sim`] plays the part of the interrupt handlers.
+pub(crate) fn in_interrupt() -> bool {
+    matches!(context(), Some((_, Holder::Harness)))
+}
+
+/// Get the stack pointer for the running task
+///
+/// Tasks run on their thread's stack, so the scheduler has to use whatever
+/// it stored last.
+pub(crate) fn current_stack_pointer() -> Option<usize> {
+    None
+}
+
+/// Get the scheduler that the current thread is simulating, if any
+pub(crate) fn thread_scheduler() -> Option<&'static Scheduler<dyn SchedPolicy>> {
+    context().map(|(cpu, _)| cpu.scheduler)
+}
+
+// End of File
@@ -0,0 +1,151 @@
+//! Running pets in the Non-secure world of an Armv8-M CPU with TrustZone
+//!
+//! The Cortex-M port works much the same in either world. Each task keeps the
+//! EXC_RETURN value it was interrupted with, so when a task was pre-empted
+//! whilst it was running a Secure function (EXC_RETURN.S is set) we return
+//! it to the Secure world, with the ES and DCRS bits as the hardware left
+//! them.
+//!
+//! What the Non-secure world cannot do is switch the Secure stack pointer. A
+//! task that calls Secure functions needs its own Secure stack, otherwise two
+//! tasks pre-empted inside the Secure world would share one. So, the Secure
+//! firmware provides a [`SecureContextApi`] and each such task calls
+//! [`allocate_secure_context`] before it calls into the Secure world. On
+//! every task switch we ask the Secure world to save `PSP_S` for the outgoing
+//! task, and to load `PSP_S` and `PSPLIM_S` for the incoming task.
+
+// Copyright (c) 2025 Ferrous Systems
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+use crate::{SchedPolicy, Scheduler, Task, TaskId, port};
+
+/// The Secure world services pets needs to give tasks their own Secure stack
+///
+/// Each one must be a Non-secure Callable entry point in your Secure
+/// firmware. A context is identified by a non-zero handle, chosen by the
+/// Secure world.
+pub struct SecureContextApi {
+    /// Allocate a Secure stack of at least `stack_size` bytes
+    ///
+    /// Returns a handle for the new context, or zero if there is no space.
+    pub alloc: unsafe extern "C" fn(stack_size: u32) -> u32,
+    /// Save the current value of `PSP_S` into the given context
+    pub save: unsafe extern "C" fn(context: u32),
+    /// Load `PSP_S` and `PSPLIM_S` from the given context
+    pub load: unsafe extern "C" fn(context: u32),
+}
+
+/// Why we couldn't give a task a Secure context
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SecureContextError {
+    /// Nobody called [`set_secure_context_api`]
+    NoApi,
+    /// We weren't called from a task
+    NotATask,
+    /// This task already has a Secure context
+    AlreadyAllocated,
+    /// The Secure world has no space for another context
+    OutOfMemory,
+}
+
+/// The installed Secure context API, or null if there isn't one
+static SECURE_CONTEXT_API: AtomicPtr<SecureContextApi> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Tell pets how to manage Secure contexts
+///
+/// Call this before starting the scheduler.
+///
+/// # Safety
+///
+/// The functions in `api` must be Non-secure Callable entry points which
+/// behave as described on [`SecureContextApi`]. We call them from the PendSV
+/// handler.
+pub unsafe fn set_secure_context_api(api: &'static SecureContextApi) {
+    SECURE_CONTEXT_API.store(
+        api as *const SecureContextApi as *mut SecureContextApi,
+        Ordering::Release,
+    );
+}
+
+/// Get the installed Secure context API, if any
+fn api() -> Option<&'static SecureContextApi> {
+    let api_ptr = SECURE_CONTEXT_API.load(Ordering::Acquire);
+    if api_ptr.is_null() {
+        None
+    } else {
+        // SAFETY: Only [`set_secure_context_api`] writes to
+        // [`SECURE_CONTEXT_API`], and it always writes a valid `'static`
+        // reference.
+        Some(unsafe { &*api_ptr })
+    }
+}
+
+/// Give the calling task its own Secure stack, of at least `stack_size` bytes
+///
+/// A task must do this before it calls any Secure function, and it can only
+/// do it once.
+pub fn allocate_secure_context(stack_size: usize) -> Result<(), SecureContextError> {
+    let api = api().ok_or(SecureContextError::NoApi)?;
+    let scheduler = Scheduler::get_scheduler().ok_or(SecureContextError::NotATask)?;
+    let task = scheduler
+        .task_list()
+        .get(scheduler.current_task_id().index())
+        .ok_or(SecureContextError::NotATask)?;
+    // We mustn't be switched out between getting the context and loading it
+    port::critical_section(|| {
+        if task.secure_context() != 0 {
+            return Err(SecureContextError::AlreadyAllocated);
+        }
+        // SAFETY: `set_secure_context_api` said this is fine to call
+        let context = unsafe { (api.alloc)(stack_size as u32) };
+        if context == 0 {
+            return Err(SecureContextError::OutOfMemory);
+        }
+        debug!(
+            "Task {} has Secure context {}",
+            scheduler.current_task_id(),
+            context
+        );
+        task.set_secure_context(context);
+        // SAFETY: `set_secure_context_api` said this is fine to call, and
+        // the Secure world just gave us this context
+        unsafe {
+            (api.load)(context);
+        }
+        Ok(())
+    })
+}
+
+/// Save the Secure context of the outgoing task, and load the incoming one
+///
+/// Called by the PendSV handler, after the scheduler has picked the next task
+/// but before the `current_task` field is updated. The `next_task` field
+/// might have changed since the pick, so we're told which task is incoming.
+pub(crate) fn switch_secure_context(scheduler: &Scheduler<dyn SchedPolicy>, next_task: TaskId) {
+    let Some(api) = api() else {
+        return;
+    };
+    let task_list = scheduler.task_list();
+    let context_of = |task: Option<&Task>| task.map_or(0, Task::secure_context);
+    let outgoing = context_of(task_list.get(scheduler.current_task_id().index()));
+    let incoming = context_of(task_list.get(next_task.index()));
+    if outgoing != 0 {
+        // SAFETY: `set_secure_context_api` said this is fine to call, and
+        // the Secure world gave us this context
+        unsafe {
+            (api.save)(outgoing);
+        }
+    }
+    if incoming != 0 {
+        // SAFETY: `set_secure_context_api` said this is fine to call, and
+        // the Secure world gave us this context
+        unsafe {
+            (api.load)(incoming);
+        }
+    }
+}
+
+// End of File
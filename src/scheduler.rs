@@ -3,17 +3,28 @@
 // Copyright (c) 2025 Ferrous Systems
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use core::sync::atomic::{AtomicPtr, AtomicU32, AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicUsize, Ordering};
 
-use crate::{StackPusher, Task};
+use crate::{
+    CpuUsage, RoundRobin, SchedPolicy, SchedView, Task, TaskInfo, TaskState, TraceEvent, port,
+};
 
 /// The location of our one and only [`Scheduler`] object.
 ///
 /// We need this so that the free-standing PendSV handler knows where all our system state is.
-pub(crate) static SCHEDULER_PTR: AtomicPtr<Scheduler> = AtomicPtr::new(core::ptr::null_mut());
+pub(crate) static SCHEDULER_PTR: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Our `forget_policy` function, for the policy of the scheduler in
+/// [`SCHEDULER_PTR`]
+///
+/// Null until the scheduler is started.
+static FORGET_POLICY_FN: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+
+/// The type of [`forget_policy`]
+type ForgetPolicyFn = unsafe fn(*const ()) -> &'static Scheduler<dyn SchedPolicy>;
 
 /// Represents a Task
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct TaskId(usize);
 
 impl TaskId {
@@ -29,12 +40,32 @@ impl TaskId {
     pub(crate) const fn invalid() -> TaskId {
         TaskId(Self::INVALID_ID)
     }
+
+    /// Create a Task ID for the given index into the task list
+    pub const fn new(index: usize) -> TaskId {
+        TaskId(index)
+    }
+
+    /// Get the index into the task list for this Task ID
+    pub const fn index(self) -> usize {
+        self.0
+    }
+
+    /// Get the name of the task with this Task ID, if it has one
+    ///
+    /// Only works once the scheduler is running.
+    pub fn name(self) -> Option<&'static str> {
+        Scheduler::get_scheduler()?.task_list.get(self.0)?.name()
+    }
 }
 
+#[cfg(feature = "defmt")]
 impl defmt::Format for TaskId {
     fn format(&self, fmt: defmt::Formatter) {
         if self.is_invalid() {
             defmt::write!(fmt, "T---");
+        } else if let Some(name) = self.name() {
+            defmt::write!(fmt, "{=str}", name);
         } else {
             defmt::write!(fmt, "T{=usize:03}", self.0);
         }
@@ -45,6 +76,8 @@ impl core::fmt::Display for TaskId {
     fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         if self.is_invalid() {
             write!(fmt, "T---")
+        } else if let Some(name) = self.name() {
+            write!(fmt, "{}", name)
         } else {
             write!(fmt, "T{:03}", self.0)
         }
@@ -54,11 +87,20 @@ impl core::fmt::Display for TaskId {
 /// A pre-emptive task-switching scheduler
 ///
 /// It time slices tasks in a round-robin fashion, whether or not they have work to do.
+/// Each task runs for its [quantum](Task::with_quantum) before the next task
+/// gets a turn, unless it waits first. You can pick the next task some other
+/// way with [`Scheduler::with_policy`], which gives you a `Scheduler<P>` for
+/// your policy `P`.
+///
+/// Whilst it runs, pets reaches the scheduler through a global pointer, from
+/// interrupt handlers and functions like [`delay`](crate::delay). Those see
+/// a `Scheduler<dyn SchedPolicy>`, so they call the policy through a vtable.
 ///
-/// The Arm hardware will push {CPSR, PC, LR, R12, R3, R2, R1, R0} to PSP when an
-/// exception occurs. We then push the rest (R11 to R4).
+/// Everything in here is hardware-independent. Starting the scheduler,
+/// generating ticks and actually switching tasks is done by the
+/// port for the hardware we are running on.
 #[repr(C)]
-pub struct Scheduler {
+pub struct Scheduler<P: ?Sized = RoundRobin> {
     /// Which task is currently running
     current_task: AtomicUsize,
     /// Which task should PendSV switch to next
@@ -67,177 +109,189 @@ pub struct Scheduler {
     task_list: &'static [Task],
     /// Current tick count
     ticks: AtomicU32,
+    /// One less than the number of tick source counts in a tick
+    systicks_per_sched_tick: AtomicU32,
+    /// The timestamp at which we last charged time to a task (or to idle)
+    last_charge: AtomicU32,
+    /// Is the CPU currently sleeping because no tasks are runnable?
+    idle: AtomicBool,
+    /// How long we have been idle since the last tick, in SysTick counts
+    idle_time: AtomicU32,
+    /// How long we have been idle recently, in SysTick counts
+    ///
+    /// See [`crate::usage::age`].
+    recent_idle_time: AtomicU32,
+    /// How many ticks are left in the current task's time slice
+    slice_left: AtomicU32,
+    /// Decides which task runs next
+    ///
+    /// This must be the last field, so that it can be unsized, and so that
+    /// the offsets of the other fields are the same whatever the policy.
+    policy: P,
 }
 
 impl Scheduler {
     /// The offset, in bytes, to the `current_task` field
+    ///
+    /// This is the same for every policy.
+    #[cfg(pets_port = "cortex-m")]
     pub(crate) const CURRENT_TASK_OFFSET: usize = core::mem::offset_of!(Scheduler, current_task);
 
-    /// The offset, in bytes, to the `next_task` field
-    pub(crate) const NEXT_TASK_OFFSET: usize = core::mem::offset_of!(Scheduler, next_task);
-
     /// The offset, in bytes, to the `tasks` field
-    pub(crate) const TASK_LIST_OFFSET: usize = core::mem::offset_of!(Scheduler, task_list);
-
-    /// This is the minimum stack we can support, because of the state we need to push
     ///
-    /// Make space for sixteen 32-bit registers in the task state, plus some
-    /// headroom
-    #[cfg(arm_abi = "eabi")]
-    pub(crate) const MIN_STACK_SIZE: usize = (4 * 16) + 8;
+    /// This is the same for every policy.
+    #[cfg(pets_port = "cortex-m")]
+    pub(crate) const TASK_LIST_OFFSET: usize = core::mem::offset_of!(Scheduler, task_list);
 
     /// This is the minimum stack we can support, because of the state we need to push
-    ///
-    /// Make space for sixteen 32-bit registers, thirty-two 32-bit FPU
-    /// registers, plus FPU status register, in the task state, plus some
-    /// headroom
-    #[cfg(arm_abi = "eabihf")]
-    pub(crate) const MIN_STACK_SIZE: usize = (4 * 49) + 8;
+    pub(crate) const MIN_STACK_SIZE: usize = port::MIN_STACK_SIZE;
 
-    /// The value of the Processor Status Register when a task starts
+    /// Roughly how many of the most recent scheduler ticks the CPU usage
+    /// figures cover
     ///
-    /// The only bit we need to set is the T bit, to indicate that the
-    /// task should run in Thumb mode (the only supported mode on Armv7-M)
-    const DEFAULT_CPSR: u32 = 1 << 24;
+    /// The window slides along by one tick every tick. Rather than keep a
+    /// figure for each tick in the window, each tick we knock
+    /// 1/`USAGE_WINDOW_TICKS` off the figures and add on the latest tick, so
+    /// older ticks fade out gradually. Must be a power of two. See
+    /// [`crate::cpu_usage`].
+    pub const USAGE_WINDOW_TICKS: u32 = 32;
 
     /// Build the scheduler
     pub const fn new(task_list: &'static [Task]) -> Scheduler {
+        Scheduler::build(task_list, RoundRobin)
+    }
+
+    /// Use the given policy to decide which task runs next
+    ///
+    /// The default is [`RoundRobin`].
+    pub const fn with_policy<P: SchedPolicy>(self, policy: P) -> Scheduler<P> {
+        Scheduler::build(self.task_list, policy)
+    }
+}
+
+impl<P: SchedPolicy> Scheduler<P> {
+    /// Build a scheduler with the given policy
+    const fn build(task_list: &'static [Task], policy: P) -> Scheduler<P> {
         // Cannot schedule without at least one task
-        assert!(!task_list.is_empty());
+        assert!(!task_list.is_empty(), "Scheduler needs at least one task");
         Scheduler {
             task_list,
             current_task: AtomicUsize::new(usize::MAX),
             next_task: AtomicUsize::new(0),
             ticks: AtomicU32::new(0),
+            systicks_per_sched_tick: AtomicU32::new(0),
+            last_charge: AtomicU32::new(0),
+            idle: AtomicBool::new(false),
+            idle_time: AtomicU32::new(0),
+            recent_idle_time: AtomicU32::new(0),
+            slice_left: AtomicU32::new(0),
+            policy,
         }
     }
 
-    /// Run the scheduler
+    /// Mark the scheduler as started, and make it the global scheduler
     ///
-    /// You may only call this once, and you should call it from `fn main()`
-    /// once all your hardware is configured. We should be in Privileged
-    /// Thread mode on the Main stack.
-    pub fn start(&self, mut syst: cortex_m::peripheral::SYST, systicks_per_sched_tick: u32) -> ! {
-        if self.current_task.load(Ordering::SeqCst) != usize::MAX {
-            panic!("Tried to re-start scheduler!");
-        }
-
-        // remember where this object is - it cannot move because we do not exit this function
-        defmt::info!(
-            "SCHEDULER_PTR @ {=usize:08x}",
+    /// Panics if the scheduler was already started. The caller must ensure
+    /// the scheduler never moves after this is called - usually by never
+    /// returning.
+    #[cfg_attr(pets_port = "sim", allow(dead_code))]
+    pub(crate) fn register(&self, systicks_per_sched_tick: u32)
+    where
+        P: 'static,
+    {
+        self.prepare_start(systicks_per_sched_tick);
+
+        // remember where this object is
+        info!(
+            "SCHEDULER_PTR @ {:08x}",
             core::ptr::addr_of!(SCHEDULER_PTR) as usize
         );
-        let self_addr = self as *const Scheduler as *mut Scheduler;
-        defmt::info!("Scheduler @ {=usize:08x}", self_addr as usize);
+        let self_addr = self as *const Scheduler<P> as *mut ();
+        info!("Scheduler @ {:08x}", self_addr as usize);
+        FORGET_POLICY_FN.store(
+            forget_policy::<P> as ForgetPolicyFn as *mut (),
+            Ordering::Relaxed,
+        );
         SCHEDULER_PTR.store(self_addr, Ordering::Release);
+    }
+}
 
-        // Must do this /after/ setting SCHEDULER_PTR because the SysTick
-        // exception handler will use SCHEDULER_PTR
-        syst.set_reload(systicks_per_sched_tick);
-        syst.set_clock_source(cortex_m::peripheral::syst::SystClkSource::Core);
-        syst.clear_current();
-        syst.enable_counter();
-        syst.enable_interrupt();
-
-        // We need to push some empty state into each task stack
-        for (task_idx, task) in self.task_list.iter().enumerate() {
-            let old_stack_top = task.stack();
-            defmt::info!(
-                "Init task frame {=usize}, with stack @ 0x{=usize:08x}",
-                task_idx,
-                old_stack_top as usize
-            );
-
-            // SAFETY: The task constructor does not let us make tasks with
-            // stacks that are too small.
-            let mut stack_pusher = unsafe { StackPusher::new(old_stack_top) };
-
-            // Standard Arm exception frame
-
-            // CPSR
-            stack_pusher.push(Self::DEFAULT_CPSR);
-            // PC
-            stack_pusher.push(task.entry_fn() as usize as u32);
-            // LR
-            stack_pusher.push(0);
-            // R12
-            stack_pusher.push(0);
-            // R0-R3
-            stack_pusher.push(0);
-            stack_pusher.push(0);
-            stack_pusher.push(0);
-            stack_pusher.push(0);
-
-            // Additional task state we persist
-
-            // Extra copy of LR so we can check for FPU status. This copy does
-            // not have the FPU bit set, so we don't need to push an Extended
-            // Frame above, or the other 16 FPU registers, into the initial
-            // state. This will return us to Thread Mode, Process Stack.
-            stack_pusher.push(0xFFFFFFFD);
-
-            // R4 - R11
-            stack_pusher.push(0);
-            stack_pusher.push(0);
-            stack_pusher.push(0);
-            stack_pusher.push(0);
-            stack_pusher.push(0);
-            stack_pusher.push(0);
-            stack_pusher.push(0);
-            stack_pusher.push(0);
-
-            // Report how much space we used
-
-            defmt::debug!(
-                "Fini task frame {=usize}, with stack @ 0x{=usize:08x}",
-                task_idx,
-                stack_pusher.current() as usize
-            );
-
-            // Set task stack pointer to the last thing we pushed
-
-            // SAFETY: the pointer we are passing is a validly aligned stack pointer
-            unsafe {
-                task.set_stack(stack_pusher.current());
-            }
+impl Scheduler<dyn SchedPolicy> {
+    /// Get the handler to the global scheduler
+    pub(crate) fn get_scheduler() -> Option<&'static Scheduler<dyn SchedPolicy>> {
+        // Simulated tasks each know which simulation they belong to
+        #[cfg(pets_port = "sim")]
+        if let Some(scheduler) = port::thread_scheduler() {
+            return Some(scheduler);
         }
 
-        // Fire the PendSV exception - the PendSV handler will select a task
-        // to run and run it
-        defmt::debug!("Hit PendSV");
-        cortex_m::peripheral::SCB::set_pendsv();
-        // flush the pipeline to ensure the PendSV fires before we reach the end of this function
-        cortex_m::asm::isb();
-        // impossible to get here
-        unreachable!();
+        // Get our stashed pointer
+        let scheduler_ptr = SCHEDULER_PTR.load(Ordering::Acquire);
+        // Are we intialised?
+        if scheduler_ptr.is_null() {
+            None
+        } else {
+            // SAFETY: Only [`Scheduler::register`] writes to
+            // [`FORGET_POLICY_FN`], and it always writes a valid
+            // [`ForgetPolicyFn`] before it sets [`SCHEDULER_PTR`].
+            let forget_policy_fn = unsafe {
+                core::mem::transmute::<*mut (), ForgetPolicyFn>(
+                    FORGET_POLICY_FN.load(Ordering::Relaxed),
+                )
+            };
+            // SAFETY: Only [`Scheduler::register`] writes to [`SCHEDULER_PTR`] and
+            // it always sets it to be a valid pointer to a [`Scheduler`] that does
+            // not move, with the policy that `forget_policy_fn` expects.
+            Some(unsafe { forget_policy_fn(scheduler_ptr) })
+        }
     }
+}
 
+impl<P: SchedPolicy + ?Sized> Scheduler<P> {
     /// Call periodically, to get the scheduler to adjust which task should run next
     ///
-    /// This is currently a round-robin with no priorities, and no sense of tasks being blocked
+    /// Tasks which are waiting are woken, and then if the current task has
+    /// used up its time slice (or the [policy](SchedPolicy::preempt) says
+    /// so) we switch to whichever task the policy picks.
     ///
-    /// Ideally call this from a SysTick handler
+    /// Ideally call this, via [`on_tick`](crate::on_tick), from a timer
+    /// interrupt handler
     pub fn sched_tick(&self) {
-        defmt::debug!("Tick!");
-        for task in self.task_list.iter() {
-            task.unpark();
-        }
+        debug!("Tick!");
 
+        // Count the tick first, so that timestamps taken from here on are correct
         #[cfg(not(any(arm_architecture = "v6-m", arm_architecture = "v8-m.base")))]
         self.ticks.fetch_add(1, Ordering::Relaxed);
 
         #[cfg(any(arm_architecture = "v6-m", arm_architecture = "v8-m.base"))]
-        cortex_m::interrupt::free(|_| {
+        port::critical_section(|| {
             self.ticks.store(
                 self.ticks.load(Ordering::Relaxed).wrapping_add(1),
                 Ordering::Relaxed,
             );
         });
 
+        self.trace(TraceEvent::Tick, self.current_task_id());
+
+        for (task_idx, task) in self.task_list.iter().enumerate() {
+            if task.unpark() {
+                self.trace(TraceEvent::Unpark, TaskId(task_idx));
+            }
+        }
+
+        #[cfg(feature = "edf")]
+        self.check_deadlines();
+
+        self.age_usage();
+
+        if self.use_slice() {
+            return;
+        }
+
         match self.pick_next_task() {
             TaskSelection::NewTask(task_id) => {
                 self.next_task.store(task_id.0, Ordering::Relaxed);
-                cortex_m::peripheral::SCB::set_pendsv();
+                port::request_task_switch(self);
             }
             TaskSelection::CurrentTask | TaskSelection::NoTasks => {
                 // nothing to
@@ -245,103 +299,450 @@ impl Scheduler {
         }
     }
 
+    /// Count a tick against the current task's time slice
+    ///
+    /// Returns `true` if the current task should keep running.
+    fn use_slice(&self) -> bool {
+        port::critical_section(|| {
+            let current_task = self.current_task.load(Ordering::Relaxed);
+            let Some(task) = self.task_list.get(current_task) else {
+                return false;
+            };
+            if task.parked() || self.policy.preempt(&self.view(current_task)) {
+                return false;
+            }
+            let slice_left = self.slice_left.load(Ordering::Relaxed).saturating_sub(1);
+            self.slice_left.store(slice_left, Ordering::Relaxed);
+            slice_left > 0
+        })
+    }
+
     /// Get current tick count
     pub fn now(&self) -> u32 {
         self.ticks.load(Ordering::Relaxed)
     }
 
+    /// Get the time since the scheduler started, in timer counts
+    ///
+    /// Unlike [`Scheduler::now`], this moves between ticks, if the port can
+    /// read its timer (e.g. SysTick, which counts CPU cycles). It only wraps
+    /// when the tick count does.
+    pub fn now_precise(&self) -> u64 {
+        port::critical_section(|| {
+            let ticks = self.now();
+            let reload = self.systicks_per_sched_tick.load(Ordering::Relaxed);
+            let tick_start = u64::from(ticks) * (u64::from(reload) + 1);
+            // The port's timestamp wraps, but it is never more than a tick or
+            // two past the start of this tick
+            let since_tick = port::timestamp(ticks, reload).wrapping_sub(tick_start as u32);
+            tick_start + u64::from(since_tick)
+        })
+    }
+
+    /// Get the number of timer counts in a tick
+    ///
+    /// This is the unit of [`Scheduler::now_precise`].
+    pub fn counts_per_tick(&self) -> u32 {
+        self.systicks_per_sched_tick
+            .load(Ordering::Relaxed)
+            .wrapping_add(1)
+    }
+
     /// Switch tasks, because this one has nothing to do right now
     pub fn yield_until_tick(&self) {
+        assert!(!port::in_interrupt(), "Only tasks can wait");
         let task_id = self.current_task.load(Ordering::Relaxed);
-        defmt::trace!("- yield_until_tick on T{=usize:03}", task_id);
+        trace!("- yield_until_tick on T{:03}", task_id);
         let task = &self.task_list[task_id];
         task.park();
+        self.trace(TraceEvent::Park, TaskId(task_id));
+        self.switch_away();
+    }
+
+    /// Switch tasks, because this one has nothing to do until it is woken
+    ///
+    /// Returns straight away if the task was woken (with
+    /// [`Scheduler::wake`]) since it last called this. Otherwise, this is
+    /// like [`Scheduler::yield_until_tick`] - the task runs again when it is
+    /// woken, or on the next tick, whichever comes first.
+    pub(crate) fn yield_until_woken(&self) {
+        assert!(!port::in_interrupt(), "Only tasks can wait");
+        let task_id = self.current_task.load(Ordering::Relaxed);
+        trace!("- yield_until_woken on T{:03}", task_id);
+        let task = &self.task_list[task_id];
+        let parked = port::critical_section(|| {
+            if task.take_woken() {
+                false
+            } else {
+                task.park();
+                true
+            }
+        });
+        if parked {
+            self.trace(TraceEvent::Park, TaskId(task_id));
+            self.switch_away();
+        }
+    }
+
+    /// Wake the given task, if it is waiting
+    ///
+    /// You can call this from a task or from an interrupt handler. If the CPU
+    /// is idle, the woken task runs straight away - otherwise it runs when it
+    /// gets its turn.
+    pub(crate) fn wake(&self, task_id: TaskId) {
+        let Some(task) = self.task_list.get(task_id.0) else {
+            return;
+        };
+        task.set_woken();
+        if !task.unpark() {
+            return;
+        }
+        self.trace(TraceEvent::Unpark, task_id);
+        // If the current task is parked, the CPU is idle (or soon will be)
+        if self.current_task_parked()
+            && let TaskSelection::NewTask(task_id) = self.pick_next_task()
+        {
+            self.next_task.store(task_id.0, Ordering::Relaxed);
+            port::request_task_switch(self);
+        }
+    }
+
+    /// Count the jobs which are still running at their deadline
+    #[cfg(feature = "edf")]
+    fn check_deadlines(&self) {
+        let now = self.now();
+        for (task_idx, task) in self.task_list.iter().enumerate() {
+            if port::critical_section(|| task.deadline().check(now)) {
+                info!("Task {} missed its deadline", TaskId(task_idx));
+            }
+        }
+    }
+
+    /// Finish the current task's job, and work out when its next job starts
+    ///
+    /// Returns the tick at which the next job is released. Panics if the
+    /// current task has no deadline.
+    #[cfg(feature = "edf")]
+    pub(crate) fn finish_job(&self) -> u32 {
+        let task_id = self.current_task_id();
+        let task = &self.task_list[task_id.0];
+        assert!(task.deadline().exists(), "Task has no deadline");
+        let (release, late) = port::critical_section(|| task.deadline().finish_job(self.now()));
+        if late {
+            info!("Task {} missed its deadline", task_id);
+        }
+        release
+    }
+
+    /// Wake the given task, and switch to it as soon as possible
+    ///
+    /// Like [`Scheduler::wake`], except the woken task goes ahead of every
+    /// other task. From an interrupt handler, it runs once the handler
+    /// returns.
+    pub(crate) fn wake_now(&self, task_id: TaskId) {
+        let Some(task) = self.task_list.get(task_id.0) else {
+            return;
+        };
+        task.set_woken();
+        if task.unpark() {
+            self.trace(TraceEvent::Unpark, task_id);
+        }
+        if self.current_task.load(Ordering::Relaxed) != task_id.0 {
+            self.next_task.store(task_id.0, Ordering::Relaxed);
+            port::request_task_switch(self);
+        }
+    }
+
+    /// Switch to another task, because the current one is parked
+    ///
+    /// If there is nothing to switch to, we sleep until an interrupt. An
+    /// interrupt might wake the current task at any point in here, in which
+    /// case we just return.
+    fn switch_away(&self) {
         match self.pick_next_task() {
             TaskSelection::NewTask(task_id) => {
                 self.next_task.store(task_id.0, Ordering::Relaxed);
-                cortex_m::peripheral::SCB::set_pendsv();
+                port::request_task_switch(self);
             }
             TaskSelection::CurrentTask => {
-                panic!("Picked a task we just parked?!");
+                assert!(
+                    !self.current_task_parked(),
+                    "Scheduling policy picked a parked task"
+                );
+                trace!("- Woken whilst parking");
             }
             TaskSelection::NoTasks => {
-                defmt::trace!("- Sleep!");
-                cortex_m::asm::wfi();
-                cortex_m::asm::isb();
+                // Everything up to now was this task, but the sleep is idle time
+                let sleep = port::critical_section(|| {
+                    if !self.current_task_parked() {
+                        return false;
+                    }
+                    self.charge_elapsed();
+                    self.idle.store(true, Ordering::Relaxed);
+                    true
+                });
+                if !sleep {
+                    trace!("- Woken whilst parking");
+                    return;
+                }
+                trace!("- Sleep!");
+                port::wait_for_interrupt();
+                // If we weren't switched out, the sleep hasn't been charged yet
+                port::critical_section(|| {
+                    self.charge_elapsed();
+                });
             }
         }
     }
 
+    /// Is the current task parked?
+    fn current_task_parked(&self) -> bool {
+        self.task_list
+            .get(self.current_task.load(Ordering::Relaxed))
+            .is_some_and(Task::parked)
+    }
+
+    /// Get the CPU usage figures for the most recent usage window
+    ///
+    /// There is room for the first `N` tasks - see [`CpuUsage`].
+    pub fn cpu_usage<const N: usize>(&self) -> CpuUsage<N> {
+        port::critical_section(|| {
+            CpuUsage::new(
+                self.task_list,
+                self.recent_idle_time.load(Ordering::Relaxed),
+            )
+        })
+    }
+
+    /// Get a snapshot of the state of every task, in task list order
+    pub fn tasks(&self) -> impl Iterator<Item = TaskInfo> + '_ {
+        let current_task = self.current_task.load(Ordering::Relaxed);
+        self.task_list
+            .iter()
+            .enumerate()
+            .map(move |(task_idx, task)| {
+                let (state, stack_pointer) = if task_idx == current_task {
+                    // The stored stack pointer is stale for the running task
+                    let stack_pointer =
+                        port::current_stack_pointer().unwrap_or(task.stack() as usize);
+                    (TaskState::Running, stack_pointer)
+                } else if task.parked() {
+                    (TaskState::Parked, task.stack() as usize)
+                } else {
+                    (TaskState::Ready, task.stack() as usize)
+                };
+                TaskInfo {
+                    id: TaskId(task_idx),
+                    name: task.name(),
+                    state,
+                    stack_pointer,
+                    stack_used: port::PAINTS_STACKS.then(|| task.stack_high_water()),
+                    stack_size: task.stack_size(),
+                    run_count: task.run_count(),
+                    last_run_tick: task.last_run_tick(),
+                    #[cfg(feature = "edf")]
+                    deadline_misses: task.deadline().misses(),
+                }
+            })
+    }
+
     /// Get the current Task ID
     pub fn current_task_id(&self) -> TaskId {
         TaskId(self.current_task.load(Ordering::Relaxed))
     }
 
-    /// Get the handler to the global scheduler
-    pub(crate) fn get_scheduler() -> Option<&'static Scheduler> {
-        // Get our stashed pointer
-        let scheduler_ptr = SCHEDULER_PTR.load(Ordering::Relaxed);
-        // Are we intialised?
-        if scheduler_ptr.is_null() {
-            None
-        } else {
-            // SAFETY: Only [`Scheduler::start`] writes to [`SCHEDULER_PTR`] and it
-            // always sets it to be a valid pointer to a [`Scheduler`] that does not
-            // move.
-            Some(unsafe { &*scheduler_ptr })
+    /// Get the ID of the task we are about to switch to
+    pub(crate) fn next_task_id(&self) -> TaskId {
+        TaskId(self.next_task.load(Ordering::Relaxed))
+    }
+
+    /// Get a timestamp with sub-tick resolution, measured in SysTick counts
+    ///
+    /// It wraps around, so only use it to measure short intervals.
+    fn timestamp(&self) -> u32 {
+        port::critical_section(|| {
+            port::timestamp(
+                self.now(),
+                self.systicks_per_sched_tick.load(Ordering::Relaxed),
+            )
+        })
+    }
+
+    /// Charge the time since we last did this to the current task (or to idle)
+    ///
+    /// Only call this from within a critical section.
+    fn charge_elapsed(&self) {
+        let now = self.timestamp();
+        let elapsed = now.wrapping_sub(self.last_charge.load(Ordering::Relaxed));
+        self.last_charge.store(now, Ordering::Relaxed);
+        if self.idle.load(Ordering::Relaxed) {
+            self.idle.store(false, Ordering::Relaxed);
+            self.idle_time.store(
+                self.idle_time.load(Ordering::Relaxed).wrapping_add(elapsed),
+                Ordering::Relaxed,
+            );
+        } else if let Some(task) = self
+            .task_list
+            .get(self.current_task.load(Ordering::Relaxed))
+        {
+            task.add_run_time(elapsed);
+        }
+    }
+
+    /// Slide the CPU usage window along by a tick
+    fn age_usage(&self) {
+        port::critical_section(|| {
+            // Make sure the window includes the task that is running right now
+            self.charge_elapsed();
+            for task in self.task_list.iter() {
+                task.age_run_time();
+            }
+            self.recent_idle_time.store(
+                crate::usage::age(
+                    self.recent_idle_time.load(Ordering::Relaxed),
+                    self.idle_time.load(Ordering::Relaxed),
+                ),
+                Ordering::Relaxed,
+            );
+            self.idle_time.store(0, Ordering::Relaxed);
+        });
+    }
+
+    /// Called by the port just before it switches tasks
+    ///
+    /// The `current_task` field still holds the outgoing task at this point.
+    /// The port must switch to `next_task`, and not re-read the `next_task`
+    /// field, which an interrupt might have changed since.
+    pub(crate) fn on_task_switch(&self, next_task: TaskId) {
+        port::critical_section(|| {
+            self.charge_elapsed();
+            if let Some(task) = self.task_list.get(next_task.index()) {
+                task.record_run(self.now());
+                self.slice_left.store(task.quantum(), Ordering::Relaxed);
+            }
+        });
+
+        let current_task = self.current_task_id();
+        if !current_task.is_invalid() {
+            self.trace(TraceEvent::SwitchOut, current_task);
         }
+        self.trace(TraceEvent::SwitchIn, next_task);
     }
 
-    /// Select the next task in the round-robin
+    /// Tell the trace hook (if one is installed) about a scheduler event
+    #[cfg(feature = "trace")]
+    fn trace(&self, event: TraceEvent, task_id: TaskId) {
+        if let Some(hook) = crate::trace::hook() {
+            hook(event, task_id, self.timestamp());
+        }
+    }
+
+    /// Tracing is disabled, so this does nothing
+    #[cfg(not(feature = "trace"))]
+    fn trace(&self, _event: TraceEvent, _task_id: TaskId) {}
+
+    /// Show the policy what it needs to know, with the given current task
     ///
-    /// Updates `self.next_task` but doesn't trigger a task switch. Set PendSV
-    /// to do that.
+    /// Only call this from within a critical section.
+    fn view(&self, current_task: usize) -> SchedView<'static> {
+        SchedView::new(
+            TaskId(current_task),
+            self.task_list,
+            self.now(),
+            self.slice_left.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Ask the policy which task should run next
     ///
-    /// Returns `true` if a new task was picked, or `false` if no tasks were available
+    /// The first task always runs first. Doesn't update `self.next_task` or
+    /// trigger a task switch - the caller should do that.
     fn pick_next_task(&self) -> TaskSelection {
-        defmt::trace!("> picking a task");
-        let task_sel = cortex_m::interrupt::free(|_cs| {
+        trace!("> picking a task");
+        let task_sel = port::critical_section(|| {
             let current_task = self.current_task.load(Ordering::Relaxed);
             if current_task == usize::MAX {
                 return TaskSelection::NewTask(TaskId(0));
             }
-            let mut selected_next_task = None;
-            let num_tasks = self.task_list.len();
-            // Go through all the tasks. We start with the one after the
-            // current task, so we don't keep pickng the same task.
-            for mut idx in (current_task + 1)..=(current_task + num_tasks) {
-                // do the wrap-around
-                while idx >= num_tasks {
-                    idx -= num_tasks;
-                }
-                let task = &self.task_list[idx];
-                // is this a task we can run right now?
-                if !task.parked() {
-                    selected_next_task = Some(idx);
-                    // no sense in checking any more tasks
-                    break;
-                }
-            }
-
-            if let Some(task_id) = selected_next_task {
-                if task_id == current_task {
-                    TaskSelection::CurrentTask
-                } else {
-                    TaskSelection::NewTask(TaskId(task_id))
-                }
-            } else {
-                TaskSelection::NoTasks
-            }
+            self.policy.select(&self.view(current_task))
         });
-
-        defmt::trace!("< picked {}", task_sel);
+        trace!("< picked {:?}", task_sel);
+        if let TaskSelection::NewTask(task_id) = task_sel {
+            assert!(
+                task_id.0 < self.task_list.len(),
+                "Scheduling policy picked a task that doesn't exist"
+            );
+        }
         task_sel
     }
+
+    /// Check the scheduler hasn't been started, and note the timer settings
+    ///
+    /// Panics if the scheduler was already started.
+    pub(crate) fn prepare_start(&self, systicks_per_sched_tick: u32) {
+        if self.current_task.load(Ordering::SeqCst) != usize::MAX {
+            panic!("Tried to re-start scheduler!");
+        }
+        self.systicks_per_sched_tick
+            .store(systicks_per_sched_tick, Ordering::Relaxed);
+    }
+
+    /// Get the list of tasks
+    pub(crate) fn task_list(&self) -> &'static [Task] {
+        self.task_list
+    }
+
+    /// Do everything the PendSV handler would do, except for touching registers
+    ///
+    /// Used by ports which don't switch tasks in assembly language.
+    #[cfg(not(pets_port = "cortex-m"))]
+    pub(crate) fn switch_task(&self) {
+        let next_task = self.next_task_id();
+        self.on_task_switch(next_task);
+        self.current_task.store(next_task.0, Ordering::Relaxed);
+    }
+}
+
+/// Turn the address of a `Scheduler<P>` into a reference to it, which can
+/// be used without knowing `P`
+///
+/// # Safety
+///
+/// `scheduler_ptr` must point to a `Scheduler<P>` which never moves.
+unsafe fn forget_policy<P: SchedPolicy + 'static>(
+    scheduler_ptr: *const (),
+) -> &'static Scheduler<dyn SchedPolicy> {
+    // SAFETY: The caller promised this is a `Scheduler<P>` that never moves
+    unsafe { &*scheduler_ptr.cast::<Scheduler<P>>() }
+}
+
+/// Select the next task in the round-robin
+///
+/// We start looking at the task after `current_task`, so we don't keep
+/// picking the same task, and we pick the first one that isn't parked. If
+/// `current_task` is `usize::MAX` then the scheduler hasn't started yet and we
+/// always pick the first task.
+pub(crate) fn select_next_task(current_task: usize, task_list: &[Task]) -> TaskSelection {
+    if current_task == usize::MAX {
+        return TaskSelection::NewTask(TaskId(0));
+    }
+    let num_tasks = task_list.len();
+    // Go through all the tasks, starting with the one after the current task
+    let selected_next_task = ((current_task + 1)..=(current_task + num_tasks))
+        .map(|idx| idx % num_tasks)
+        .find(|idx| !task_list[*idx].parked());
+
+    match selected_next_task {
+        Some(task_id) if task_id == current_task => TaskSelection::CurrentTask,
+        Some(task_id) => TaskSelection::NewTask(TaskId(task_id)),
+        None => TaskSelection::NoTasks,
+    }
 }
 
-/// Describes which task we picked
-#[derive(defmt::Format)]
-enum TaskSelection {
+/// Describes which task a [`SchedPolicy`] picked
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TaskSelection {
     /// We picked a new task - do a task switch
     NewTask(TaskId),
     /// We like the current task - no switch required
@@ -350,4 +751,201 @@ enum TaskSelection {
     NoTasks,
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Stack;
+
+    /// A task entry function that is never called
+    fn never_runs() -> ! {
+        unreachable!()
+    }
+
+    /// Make a list of three tasks, all sharing one stack we never use
+    fn three_tasks() -> &'static [Task] {
+        static STACK: Stack<64> = Stack::new();
+        let task_list = [
+            Task::new(never_runs, &STACK),
+            Task::new(never_runs, &STACK),
+            Task::new(never_runs, &STACK),
+        ];
+        Box::leak(Box::new(task_list))
+    }
+
+    #[test]
+    fn first_pick_is_task_zero() {
+        let task_list = three_tasks();
+        assert_eq!(
+            select_next_task(usize::MAX, task_list),
+            TaskSelection::NewTask(TaskId(0))
+        );
+    }
+
+    #[test]
+    fn picks_the_next_task_round_robin() {
+        let task_list = three_tasks();
+        assert_eq!(
+            select_next_task(0, task_list),
+            TaskSelection::NewTask(TaskId(1))
+        );
+        assert_eq!(
+            select_next_task(2, task_list),
+            TaskSelection::NewTask(TaskId(0))
+        );
+    }
+
+    #[test]
+    fn skips_parked_tasks() {
+        let task_list = three_tasks();
+        task_list[1].park();
+        assert_eq!(
+            select_next_task(0, task_list),
+            TaskSelection::NewTask(TaskId(2))
+        );
+        task_list[2].park();
+        assert_eq!(select_next_task(0, task_list), TaskSelection::CurrentTask);
+        task_list[0].park();
+        assert_eq!(select_next_task(0, task_list), TaskSelection::NoTasks);
+    }
+
+    #[test]
+    fn ticks_switch_tasks() {
+        let scheduler = Scheduler::new(three_tasks());
+        assert!(scheduler.current_task_id().is_invalid());
+        let mut seen = Vec::new();
+        for _ in 0..4 {
+            scheduler.sched_tick();
+            seen.push(scheduler.current_task_id());
+        }
+        assert_eq!(seen, [TaskId(0), TaskId(1), TaskId(2), TaskId(0)]);
+        assert_eq!(scheduler.now(), 4);
+    }
+
+    #[test]
+    fn tasks_run_for_their_quantum() {
+        static STACK: Stack<64> = Stack::new();
+        let task_list: &'static [Task] = Box::leak(Box::new([
+            Task::new(never_runs, &STACK).with_quantum(3),
+            Task::new(never_runs, &STACK),
+        ]));
+        let scheduler = Scheduler::new(task_list);
+        let mut seen = Vec::new();
+        for _ in 0..6 {
+            scheduler.sched_tick();
+            seen.push(scheduler.current_task_id());
+        }
+        assert_eq!(
+            seen,
+            [
+                TaskId(0),
+                TaskId(0),
+                TaskId(0),
+                TaskId(1),
+                TaskId(0),
+                TaskId(0)
+            ]
+        );
+        // Waiting ends the time slice early
+        scheduler.yield_until_tick();
+        assert_eq!(scheduler.current_task_id(), TaskId(1));
+    }
+
+    #[test]
+    fn policies_pick_tasks() {
+        /// Always picks the task before the current one
+        struct Backwards;
+
+        impl SchedPolicy for Backwards {
+            fn select(&self, view: &SchedView) -> TaskSelection {
+                let index = view.current_task().index().checked_sub(1);
+                TaskSelection::NewTask(TaskId(index.unwrap_or(view.tasks().len() - 1)))
+            }
+        }
+
+        let scheduler = Scheduler::new(three_tasks()).with_policy(Backwards);
+        let mut seen = Vec::new();
+        for _ in 0..4 {
+            scheduler.sched_tick();
+            seen.push(scheduler.current_task_id());
+        }
+        assert_eq!(seen, [TaskId(0), TaskId(2), TaskId(1), TaskId(0)]);
+    }
+
+    #[test]
+    #[cfg(feature = "edf")]
+    fn picks_the_earliest_deadline() {
+        use crate::edf::select_next_task;
+
+        static STACK: Stack<64> = Stack::new();
+        let task_list: &'static [Task] = Box::leak(Box::new([
+            Task::new(never_runs, &STACK),
+            Task::new(never_runs, &STACK).with_deadline(20, 15),
+            Task::new(never_runs, &STACK).with_deadline(10, 3),
+        ]));
+        let view = |current_task, now| SchedView::new(TaskId(current_task), task_list, now, 1);
+        assert_eq!(
+            select_next_task(&view(0, 0)),
+            Some(TaskSelection::NewTask(TaskId(2)))
+        );
+        // Only a different task is worth pre-empting for
+        let edf = crate::EarliestDeadlineFirst;
+        assert!(edf.preempt(&view(0, 0)));
+        assert!(!edf.preempt(&view(2, 0)));
+        // A job that hasn't been released yet can't run, however early its
+        // deadline
+        task_list[2].deadline().finish_job(0);
+        assert_eq!(task_list[2].job_release(), Some(10));
+        assert_eq!(task_list[2].job_deadline(), Some(13));
+        assert_eq!(
+            select_next_task(&view(0, 5)),
+            Some(TaskSelection::NewTask(TaskId(1)))
+        );
+        assert_eq!(
+            select_next_task(&view(0, 10)),
+            Some(TaskSelection::NewTask(TaskId(2)))
+        );
+        task_list[2].park();
+        assert_eq!(
+            select_next_task(&view(1, 0)),
+            Some(TaskSelection::CurrentTask)
+        );
+        task_list[1].park();
+        assert_eq!(select_next_task(&view(1, 0)), None);
+        assert_eq!(task_list[0].job_release(), None);
+    }
+
+    #[test]
+    fn yield_parks_until_the_next_tick() {
+        let task_list = three_tasks();
+        let scheduler = Scheduler::new(task_list);
+        scheduler.sched_tick();
+        assert_eq!(scheduler.current_task_id(), TaskId(0));
+        scheduler.yield_until_tick();
+        assert_eq!(scheduler.current_task_id(), TaskId(1));
+        assert!(task_list[0].parked());
+        scheduler.yield_until_tick();
+        scheduler.yield_until_tick();
+        // Everything is parked, so we stay where we were
+        assert_eq!(scheduler.current_task_id(), TaskId(2));
+        assert!(task_list.iter().all(|task| task.parked()));
+        // The tick wakes everyone up, and moves us on
+        scheduler.sched_tick();
+        assert!(task_list.iter().all(|task| !task.parked()));
+        assert_eq!(scheduler.current_task_id(), TaskId(0));
+    }
+
+    #[test]
+    fn counts_runs() {
+        let task_list = three_tasks();
+        let scheduler = Scheduler::new(task_list);
+        for _ in 0..7 {
+            scheduler.sched_tick();
+        }
+        let run_counts: Vec<u32> = scheduler.tasks().map(|info| info.run_count).collect();
+        assert_eq!(run_counts, [3, 2, 2]);
+        let last_runs: Vec<u32> = scheduler.tasks().map(|info| info.last_run_tick).collect();
+        assert_eq!(last_runs, [7, 5, 6]);
+    }
+}
+
 // End of File
@@ -7,11 +7,13 @@ use crate::UnsafeCell;
 
 /// A task stack, with the given size `LEN` bytes.
 ///
-/// We align stacks on 8-byte boundaries, as required by AAPCS.
+/// We align stacks on 8-byte boundaries, as required by AAPCS, or on 16-byte
+/// boundaries on RISC-V, as required by its psABI.
 ///
-/// The value of `LEN` must be a multiple of 8, which is checked with an
-/// assert, to ensure the top and bottom are both 8-byte aligned.
-#[repr(align(8))]
+/// The value of `LEN` must be a multiple of the alignment, which is checked
+/// with an assert, to ensure the top and bottom are both aligned.
+#[cfg_attr(not(pets_port = "riscv"), repr(align(8)))]
+#[cfg_attr(pets_port = "riscv", repr(align(16)))]
 pub struct Stack<const LEN: usize> {
     /// The memory reserved for the task stack
     contents: UnsafeCell<[u8; LEN]>,
@@ -20,12 +22,20 @@ pub struct Stack<const LEN: usize> {
 impl<const LEN: usize> Stack<LEN> {
     /// Create a new stack
     pub const fn new() -> Self {
-        assert!(LEN.is_multiple_of(8));
+        assert!(
+            LEN.is_multiple_of(core::mem::align_of::<Self>()),
+            "Stack size must be a multiple of the stack alignment"
+        );
         Self {
             contents: UnsafeCell::new([0u8; LEN]),
         }
     }
 
+    /// Get the bottom of the stack
+    pub const fn bottom(&self) -> *mut u32 {
+        self.contents.get() as *mut u32
+    }
+
     /// Get the top of the stack
     pub const fn top(&self) -> *mut u32 {
         // SAFETY: Pointing one past this object is allowed, as this is full
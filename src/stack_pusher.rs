@@ -3,7 +3,7 @@
 // Copyright (c) 2025 Ferrous Systems
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-/// A helper for pushing things into a full-descending Arm EABI stack
+/// A helper for pushing things into a full-descending stack
 pub(crate) struct StackPusher(*mut u32);
 
 impl StackPusher {
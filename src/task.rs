@@ -6,6 +6,8 @@
 use core::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
 
 use crate::Stack;
+#[cfg(feature = "edf")]
+use crate::edf::Deadline;
 
 /// The function signature for our task entry functions.
 ///
@@ -31,17 +33,71 @@ pub struct Task {
     entry_fn: TaskEntryFn,
     /// Information about the task
     flags: AtomicU32,
-    /// Padding it out to a 16-byte structure
-    _reserved: u32,
+    /// How long this task has run for since the last tick, in SysTick counts
+    run_time: AtomicU32,
+    /// How long this task has run for recently, in SysTick counts
+    ///
+    /// See [`crate::usage::age`].
+    recent_run_time: AtomicU32,
+    /// A name for this task, for logs and debuggers
+    name: Option<&'static str>,
+    /// The lowest address in our task's stack
+    stack_bottom: *mut u32,
+    /// The size of our task's stack, in bytes
+    stack_size: u32,
+    /// How many ticks this task may run for before another task gets a turn
+    quantum: u32,
+    /// How many times this task has been switched in
+    run_count: AtomicU32,
+    /// The tick count when this task was last switched in
+    last_run_tick: AtomicU32,
+    /// The handle for this task's Secure world context, or zero if it has none
+    #[cfg_attr(
+        not(all(pets_port = "cortex-m", feature = "trustzone")),
+        allow(dead_code)
+    )]
+    secure_context: AtomicU32,
+    /// This task's period and deadline, for earliest-deadline-first scheduling
+    #[cfg(feature = "edf")]
+    deadline: Deadline,
+    /// Padding it out to a power-of-two sized structure
+    _reserved: [u32; Self::RESERVED_WORDS],
 }
 
 impl Task {
     /// The size of a task object is `pow(2, SIZE_BITS)`.
-    pub const SIZE_BITS: usize = 4;
+    #[cfg(all(target_pointer_width = "32", not(feature = "edf")))]
+    pub const SIZE_BITS: usize = 6;
+
+    /// The size of a task object is `pow(2, SIZE_BITS)`.
+    #[cfg(any(target_pointer_width = "64", feature = "edf"))]
+    pub const SIZE_BITS: usize = 7;
+
+    /// How many padding words we need to make the size work out
+    #[cfg(all(target_pointer_width = "32", not(feature = "edf")))]
+    const RESERVED_WORDS: usize = 3;
+
+    /// How many padding words we need to make the size work out
+    #[cfg(all(target_pointer_width = "32", feature = "edf"))]
+    const RESERVED_WORDS: usize = 15;
+
+    /// How many padding words we need to make the size work out
+    #[cfg(all(target_pointer_width = "64", not(feature = "edf")))]
+    const RESERVED_WORDS: usize = 13;
+
+    /// How many padding words we need to make the size work out
+    #[cfg(all(target_pointer_width = "64", feature = "edf"))]
+    const RESERVED_WORDS: usize = 9;
 
     /// The flag that indicates the task is parked
     const FLAG_PARK: u32 = 1 << 0;
 
+    /// The flag that indicates the task was woken since it last waited
+    const FLAG_WOKEN: u32 = 1 << 1;
+
+    /// The value we fill unused stack with, so we can see how much was used
+    const STACK_PAINT: u32 = 0xDEAD_C0DE;
+
     /// A compile-time check that the size of a [`Task`] is what we said it was.
     const _CHECK: () = const {
         assert!(core::mem::size_of::<Self>() == (1 << Self::SIZE_BITS));
@@ -49,20 +105,176 @@ impl Task {
 
     /// Create a new [`Task`] object
     pub const fn new<const N: usize>(entry_fn: TaskEntryFn, stack: &Stack<N>) -> Task {
-        assert!(N > crate::Scheduler::MIN_STACK_SIZE);
+        assert!(
+            N > crate::Scheduler::MIN_STACK_SIZE,
+            "Task stack is too small"
+        );
         Task {
             entry_fn,
             stack: AtomicPtr::new(stack.top()),
             flags: AtomicU32::new(0),
-            _reserved: 0,
+            run_time: AtomicU32::new(0),
+            recent_run_time: AtomicU32::new(0),
+            name: None,
+            stack_bottom: stack.bottom(),
+            stack_size: N as u32,
+            quantum: 1,
+            run_count: AtomicU32::new(0),
+            last_run_tick: AtomicU32::new(0),
+            secure_context: AtomicU32::new(0),
+            #[cfg(feature = "edf")]
+            deadline: Deadline::none(),
+            _reserved: [0; Self::RESERVED_WORDS],
         }
     }
 
+    /// Give this [`Task`] a name
+    ///
+    /// The name is used when formatting the task's [`TaskId`](crate::TaskId),
+    /// so it appears in logs instead of a number.
+    pub const fn with_name(mut self, name: &'static str) -> Task {
+        self.name = Some(name);
+        self
+    }
+
+    /// Give this [`Task`] a time slice of the given number of ticks
+    ///
+    /// Whilst it has work to do, the task runs for this many ticks before
+    /// the next task gets a turn. A compute-bound task with a longer time
+    /// slice spends less time switching tasks. The default is one tick.
+    pub const fn with_quantum(mut self, ticks: u32) -> Task {
+        assert!(ticks > 0, "Task quantum must be at least one tick");
+        self.quantum = ticks;
+        self
+    }
+
+    /// Give this [`Task`] a period and a deadline, both in ticks
+    ///
+    /// The task should do one job every `period` ticks, finishing each with
+    /// [`wait_for_next_period`](crate::wait_for_next_period), and each job
+    /// must be done within `deadline` ticks of the start of its period. The
+    /// [`EarliestDeadlineFirst`](crate::EarliestDeadlineFirst) policy runs
+    /// the task with the earliest deadline first. The deadline can't be
+    /// longer than the period.
+    #[cfg(feature = "edf")]
+    pub const fn with_deadline(mut self, period: u32, deadline: u32) -> Task {
+        assert!(deadline > 0, "Task deadline must be at least one tick");
+        assert!(
+            deadline <= period,
+            "Task deadline can't be after its period"
+        );
+        self.deadline = Deadline::new(period, deadline);
+        self
+    }
+
+    /// Get the name of this task, if it has one
+    pub const fn name(&self) -> Option<&'static str> {
+        self.name
+    }
+
     /// Get the initial entry function for this task
     pub(crate) const fn entry_fn(&self) -> TaskEntryFn {
         self.entry_fn
     }
 
+    /// Get the length of this task's time slice, in ticks
+    pub const fn quantum(&self) -> u32 {
+        self.quantum
+    }
+
+    /// Get this task's period and deadline
+    #[cfg(feature = "edf")]
+    pub(crate) const fn deadline(&self) -> &Deadline {
+        &self.deadline
+    }
+
+    /// Get the tick at which this task's current job is released, if it has
+    /// a deadline
+    ///
+    /// Whilst the task waits for its next period, this is in the future.
+    #[cfg(feature = "edf")]
+    pub fn job_release(&self) -> Option<u32> {
+        self.deadline.exists().then(|| self.deadline.release())
+    }
+
+    /// Get the tick by which this task's current job must be finished, if it
+    /// has a deadline
+    #[cfg(feature = "edf")]
+    pub fn job_deadline(&self) -> Option<u32> {
+        self.deadline.exists().then(|| self.deadline.absolute())
+    }
+
+    /// Get the size of this task's stack, in bytes
+    pub(crate) const fn stack_size(&self) -> usize {
+        self.stack_size as usize
+    }
+
+    /// Fill this task's stack with a known pattern
+    ///
+    /// # Safety
+    ///
+    /// Only call this before the task has started, because it trashes
+    /// everything on the stack.
+    #[cfg_attr(pets_port = "sim", allow(dead_code))]
+    pub(crate) unsafe fn paint_stack(&self) {
+        for idx in 0..(self.stack_size() / 4) {
+            // SAFETY: We are within the bounds of the stack, and the caller
+            // promised that nothing is using it.
+            unsafe {
+                self.stack_bottom.add(idx).write_volatile(Self::STACK_PAINT);
+            }
+        }
+    }
+
+    /// Work out the most stack this task has ever used, in bytes
+    ///
+    /// Only accurate if [`Task::paint_stack`] was called before the task started.
+    pub(crate) fn stack_high_water(&self) -> usize {
+        let num_words = self.stack_size() / 4;
+        let unused_words = (0..num_words)
+            .take_while(|idx| {
+                // SAFETY: We are within the bounds of the stack, and reading
+                // a word is harmless even if the task is using it.
+                let value = unsafe { self.stack_bottom.add(*idx).read_volatile() };
+                value == Self::STACK_PAINT
+            })
+            .count();
+        (num_words - unused_words) * 4
+    }
+
+    /// Note that this task has just been switched in
+    ///
+    /// Only call this from within a critical section.
+    pub(crate) fn record_run(&self, now: u32) {
+        self.run_count.store(
+            self.run_count.load(Ordering::Relaxed).wrapping_add(1),
+            Ordering::Relaxed,
+        );
+        self.last_run_tick.store(now, Ordering::Relaxed);
+    }
+
+    /// How many times has this task been switched in?
+    pub fn run_count(&self) -> u32 {
+        self.run_count.load(Ordering::Relaxed)
+    }
+
+    /// What was the tick count when this task was last switched in?
+    pub fn last_run_tick(&self) -> u32 {
+        self.last_run_tick.load(Ordering::Relaxed)
+    }
+
+    /// Get the handle for this task's Secure world context, or zero if it has none
+    #[cfg(all(pets_port = "cortex-m", feature = "trustzone"))]
+    pub(crate) fn secure_context(&self) -> u32 {
+        self.secure_context.load(Ordering::Relaxed)
+    }
+
+    /// Set the handle for this task's Secure world context
+    #[cfg(all(pets_port = "cortex-m", feature = "trustzone"))]
+    pub(crate) fn set_secure_context(&self, context: u32) {
+        self.secure_context.store(context, Ordering::Relaxed)
+    }
+
     /// Get the current stack pointer for this task
     pub(crate) fn stack(&self) -> *mut u32 {
         self.stack.load(Ordering::Relaxed)
@@ -75,6 +287,7 @@ impl Task {
     /// The task will execute using the stack given, so it must point to the
     /// last item in a valid Arm EABI stack, with a full pets Stack Frame
     /// proceeding it.
+    #[cfg_attr(pets_port = "sim", allow(dead_code))]
     pub(crate) unsafe fn set_stack(&self, new_stack: *mut u32) {
         self.stack.store(new_stack, Ordering::Relaxed)
     }
@@ -83,40 +296,100 @@ impl Task {
     ///
     /// If a task is parked, it will not be resumed until the next tick.
     pub(crate) fn park(&self) {
-        #[cfg(not(any(arm_architecture = "v6-m", arm_architecture = "v8-m.base")))]
-        self.flags.fetch_or(Self::FLAG_PARK, Ordering::Relaxed);
-
-        #[cfg(any(arm_architecture = "v6-m", arm_architecture = "v8-m.base"))]
-        cortex_m::interrupt::free(|_| {
-            self.flags.store(
-                self.flags.load(Ordering::Relaxed) | Self::FLAG_PARK,
-                Ordering::Relaxed,
-            );
-        });
+        self.set_flags(Self::FLAG_PARK);
     }
 
     /// Is this task parked?
     ///
-    /// See [`Task::park`]
-    pub(crate) fn parked(&self) -> bool {
+    /// A parked task is waiting, and mustn't run until something unparks it.
+    /// Every tick unparks every task.
+    pub fn parked(&self) -> bool {
         (self.flags.load(Ordering::Relaxed) & Self::FLAG_PARK) != 0
     }
 
     /// Unpark this task
     ///
-    /// See [`Task::park`]
-    pub(crate) fn unpark(&self) {
+    /// Returns `true` if the task was parked. See [`Task::park`]
+    pub(crate) fn unpark(&self) -> bool {
+        (self.clear_flags(Self::FLAG_PARK) & Self::FLAG_PARK) != 0
+    }
+
+    /// Note that something has woken this task
+    ///
+    /// See [`Task::take_woken`]
+    pub(crate) fn set_woken(&self) {
+        self.set_flags(Self::FLAG_WOKEN);
+    }
+
+    /// Has this task been woken since we last asked?
+    ///
+    /// Clears the flag set by [`Task::set_woken`].
+    pub(crate) fn take_woken(&self) -> bool {
+        (self.clear_flags(Self::FLAG_WOKEN) & Self::FLAG_WOKEN) != 0
+    }
+
+    /// Set the given bits in our flags
+    fn set_flags(&self, mask: u32) {
+        #[cfg(not(any(arm_architecture = "v6-m", arm_architecture = "v8-m.base")))]
+        self.flags.fetch_or(mask, Ordering::Relaxed);
+
+        #[cfg(any(arm_architecture = "v6-m", arm_architecture = "v8-m.base"))]
+        crate::port::critical_section(|| {
+            self.flags
+                .store(self.flags.load(Ordering::Relaxed) | mask, Ordering::Relaxed);
+        });
+    }
+
+    /// Clear the given bits in our flags, returning the old flags
+    fn clear_flags(&self, mask: u32) -> u32 {
         #[cfg(not(any(arm_architecture = "v6-m", arm_architecture = "v8-m.base")))]
-        self.flags.fetch_and(!Self::FLAG_PARK, Ordering::Relaxed);
+        let old_flags = self.flags.fetch_and(!mask, Ordering::Relaxed);
 
         #[cfg(any(arm_architecture = "v6-m", arm_architecture = "v8-m.base"))]
-        cortex_m::interrupt::free(|_| {
-            self.flags.store(
-                self.flags.load(Ordering::Relaxed) & !Self::FLAG_PARK,
-                Ordering::Relaxed,
-            );
+        let old_flags = crate::port::critical_section(|| {
+            let old_flags = self.flags.load(Ordering::Relaxed);
+            self.flags.store(old_flags & !mask, Ordering::Relaxed);
+            old_flags
         });
+
+        old_flags
+    }
+
+    /// Add some time to this task's run time since the last tick
+    ///
+    /// Only call this from within a critical section.
+    pub(crate) fn add_run_time(&self, systicks: u32) {
+        self.run_time.store(
+            self.run_time.load(Ordering::Relaxed).wrapping_add(systicks),
+            Ordering::Relaxed,
+        );
+    }
+
+    /// Fold the run time since the last tick into the recent run time
+    ///
+    /// Only call this from within a critical section.
+    pub(crate) fn age_run_time(&self) {
+        self.recent_run_time.store(
+            crate::usage::age(
+                self.recent_run_time.load(Ordering::Relaxed),
+                self.run_time.load(Ordering::Relaxed),
+            ),
+            Ordering::Relaxed,
+        );
+        self.run_time.store(0, Ordering::Relaxed);
+    }
+
+    /// How long has this task run for recently?
+    ///
+    /// Measured in SysTick counts. See [`crate::usage::age`].
+    pub(crate) fn recent_run_time(&self) -> u32 {
+        self.recent_run_time.load(Ordering::Relaxed)
     }
 }
 
+/// SAFETY: The only thing that stops a Task from being Sync is the pointer to
+/// the bottom of the stack, and we only use that to paint and inspect the
+/// stack.
+unsafe impl Sync for Task {}
+
 // End of File
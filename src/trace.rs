@@ -0,0 +1,104 @@
+//! Holds the scheduler trace hook and [`TraceEvent`] type
+//!
+//! Enable the `trace` feature, and call [`set_trace_hook`] with a function
+//! of your own, and the scheduler will call your function every time it
+//! switches, parks or unparks a task, and on every tick. This is much cheaper
+//! than logging each event as text, so it can be used to capture a timeline
+//! of which task ran when.
+
+// Copyright (c) 2025 Ferrous Systems
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+#[cfg(feature = "trace")]
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+#[cfg(feature = "trace")]
+use crate::TaskId;
+
+/// Something the scheduler did, that a trace hook might want to know about
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum TraceEvent {
+    /// The task is about to start running
+    SwitchIn,
+    /// The task has stopped running
+    SwitchOut,
+    /// The task has nothing to do until the next tick
+    Park,
+    /// The task is runnable again
+    Unpark,
+    /// The scheduler ticked, whilst the task was running
+    Tick,
+}
+
+/// The function signature for a trace hook
+///
+/// You are given the event, the task it relates to, and a timestamp in counts
+/// of the timer that generates the scheduler tick: your `TickSource` on
+/// Cortex-M, the CLINT's machine timer on RISC-V, or the Generic Timer on
+/// Armv8-R (on Armv7-R, the counts you gave `Scheduler::start`). There are
+/// [`counts_per_tick`](crate::Scheduler::counts_per_tick) of them in a tick.
+/// The timestamp wraps around, and has the resolution of that timer, not of
+/// the scheduler tick.
+///
+/// Your hook may be called from a task (e.g. [`TraceEvent::Park`], when a
+/// task waits) or from any interrupt handler (e.g. the tick, the task switch,
+/// or [`TraceEvent::Unpark`] from a handler which wakes a task). A more
+/// urgent interrupt can call it again whilst it is running, so it must be
+/// re-entrant, and it should be quick.
+#[cfg(feature = "trace")]
+pub type TraceHookFn = fn(TraceEvent, TaskId, u32);
+
+/// The installed trace hook, or null if there isn't one
+#[cfg(feature = "trace")]
+static TRACE_HOOK: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Install a function to be called on every scheduler event
+///
+/// Replaces any previously installed hook.
+#[cfg(feature = "trace")]
+pub fn set_trace_hook(hook: TraceHookFn) {
+    TRACE_HOOK.store(hook as *mut (), Ordering::Release);
+}
+
+/// A trace hook which sends every event out over defmt
+///
+/// Each event is a single, small, defmt frame which is printed regardless of
+/// the log level. Switching a task in also sends the task's name, so the
+/// `pets-trace` tool in this repository can label the timeline it makes from
+/// the decoded output.
+#[cfg(all(feature = "trace", feature = "defmt"))]
+pub fn defmt_trace_hook(event: TraceEvent, task_id: TaskId, timestamp: u32) {
+    if event == TraceEvent::SwitchIn {
+        defmt::println!(
+            "PETS-TRACE {=u8} {=usize} {=u32} {}",
+            event as u8,
+            task_id.index(),
+            timestamp,
+            task_id
+        );
+    } else {
+        defmt::println!(
+            "PETS-TRACE {=u8} {=usize} {=u32}",
+            event as u8,
+            task_id.index(),
+            timestamp
+        );
+    }
+}
+
+/// Get the installed trace hook, if any
+#[cfg(feature = "trace")]
+pub(crate) fn hook() -> Option<TraceHookFn> {
+    let hook_ptr = TRACE_HOOK.load(Ordering::Acquire);
+    if hook_ptr.is_null() {
+        None
+    } else {
+        // SAFETY: Only [`set_trace_hook`] writes to [`TRACE_HOOK`], and it
+        // always writes a valid `TraceHookFn`.
+        Some(unsafe { core::mem::transmute::<*mut (), TraceHookFn>(hook_ptr) })
+    }
+}
+
+// End of File
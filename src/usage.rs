@@ -0,0 +1,118 @@
+//! Holds the [`CpuUsage`] type and methods
+
+// Copyright (c) 2025 Ferrous Systems
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::{Scheduler, Task, TaskId};
+
+/// How far to shift a recent time right, to get the part we forget each tick
+const USAGE_SHIFT: u32 = Scheduler::USAGE_WINDOW_TICKS.ilog2();
+
+/// CPU usage figures, from the most recent usage window
+///
+/// The scheduler measures how long each task runs for (and how long the CPU
+/// sleeps for with nothing to do) over a window covering roughly the last
+/// [`Scheduler::USAGE_WINDOW_TICKS`] ticks. The window slides along every
+/// tick, so this always describes the recent past, and a task that has only
+/// just got busy shows up straight away.
+///
+/// The figures are all copied at once, so they add up. There is room for the
+/// first `N` tasks - any after that are left out, but the time they ran for
+/// still counts towards the window.
+pub struct CpuUsage<const N: usize> {
+    /// How long each of the first `N` tasks ran for, in SysTick counts
+    run_times: [u32; N],
+    /// How many entries in `run_times` are tasks
+    num_tasks: usize,
+    /// How long the CPU was idle, in SysTick counts
+    idle_time: u32,
+    /// How long the window was, in SysTick counts
+    total_time: u64,
+}
+
+impl<const N: usize> CpuUsage<N> {
+    /// Copy the usage figures for the given tasks
+    ///
+    /// Only call this from within a critical section, so that a tick can't
+    /// slide the window along whilst we copy.
+    pub(crate) fn new(task_list: &[Task], idle_time: u32) -> CpuUsage<N> {
+        let mut run_times = [0; N];
+        for (run_time, task) in run_times.iter_mut().zip(task_list) {
+            *run_time = task.recent_run_time();
+        }
+        let total_time = task_list
+            .iter()
+            .map(|task| u64::from(task.recent_run_time()))
+            .sum::<u64>()
+            + u64::from(idle_time);
+        CpuUsage {
+            run_times,
+            num_tasks: task_list.len().min(N),
+            idle_time,
+            total_time,
+        }
+    }
+
+    /// What percentage of the window was spent running the given task?
+    ///
+    /// Returns `None` if the task ID is not valid, or if the task was left
+    /// out because there was no room for it.
+    pub fn task_percent(&self, task_id: TaskId) -> Option<u8> {
+        self.run_times[..self.num_tasks]
+            .get(task_id.index())
+            .map(|run_time| self.percent(*run_time))
+    }
+
+    /// What percentage of the window was spent idle?
+    pub fn idle_percent(&self) -> u8 {
+        self.percent(self.idle_time)
+    }
+
+    /// Iterate through the usage percentage of every task we have room for,
+    /// in task list order
+    pub fn iter(&self) -> impl Iterator<Item = (TaskId, u8)> + '_ {
+        self.run_times[..self.num_tasks]
+            .iter()
+            .enumerate()
+            .map(|(idx, run_time)| (TaskId::new(idx), self.percent(*run_time)))
+    }
+
+    /// Convert a time in SysTick counts into a percentage of the window
+    fn percent(&self, time: u32) -> u8 {
+        let percent = (u64::from(time) * 100)
+            .checked_div(self.total_time)
+            .unwrap_or(0);
+        u8::try_from(percent.min(100)).unwrap_or(100)
+    }
+}
+
+/// Slide a recent time along by a tick, given the time spent in that tick
+///
+/// The recent time is an exponentially weighted sum: each tick it loses
+/// 1/[`Scheduler::USAGE_WINDOW_TICKS`] of itself, and gains the latest
+/// tick's time. Something which takes a steady share of every tick settles
+/// at that share of `USAGE_WINDOW_TICKS` ticks' worth of time.
+pub(crate) fn age(recent: u32, latest: u32) -> u32 {
+    (recent - (recent >> USAGE_SHIFT)).saturating_add(latest)
+}
+
+#[cfg(feature = "defmt")]
+impl<const N: usize> defmt::Format for CpuUsage<N> {
+    fn format(&self, fmt: defmt::Formatter) {
+        for (task_id, percent) in self.iter() {
+            defmt::write!(fmt, "{}={=u8}% ", task_id, percent);
+        }
+        defmt::write!(fmt, "idle={=u8}%", self.idle_percent());
+    }
+}
+
+impl<const N: usize> core::fmt::Display for CpuUsage<N> {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for (task_id, percent) in self.iter() {
+            write!(fmt, "{}={}% ", task_id, percent)?;
+        }
+        write!(fmt, "idle={}%", self.idle_percent())
+    }
+}
+
+// End of File
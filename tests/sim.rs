@@ -0,0 +1,674 @@
+//! Tests for running tasks in a simulation, on the host
+
+// Copyright (c) 2025 Ferrous Systems
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::sync::{
+    Mutex,
+    atomic::{AtomicBool, Ordering},
+};
+
+use pets::{
+    Deferred, Scheduler, Stack, Task, TaskId,
+    asynch::{self, Queue, Semaphore},
+    sim::{self, Run, Sim},
+};
+
+#[test]
+fn delays_wake_on_time() {
+    static STACK: Stack<64> = Stack::new();
+    static TASK_LIST: [Task; 2] = [Task::new(fast, &STACK), Task::new(slow, &STACK)];
+    static SCHEDULER: Scheduler = Scheduler::new(&TASK_LIST);
+    static LOG: Mutex<Vec<(u32, &str)>> = Mutex::new(Vec::new());
+
+    fn fast() -> ! {
+        loop {
+            LOG.lock().unwrap().push((pets::now(), "fast"));
+            pets::delay(2);
+        }
+    }
+
+    fn slow() -> ! {
+        loop {
+            LOG.lock().unwrap().push((pets::now(), "slow"));
+            pets::delay(3);
+        }
+    }
+
+    let sim = Sim::new(&SCHEDULER);
+    sim.advance(6);
+    assert_eq!(sim.now(), 6);
+    assert_eq!(
+        *LOG.lock().unwrap(),
+        [
+            (0, "fast"),
+            (0, "slow"),
+            (2, "fast"),
+            (3, "slow"),
+            (4, "fast"),
+            (6, "fast"),
+            (6, "slow"),
+        ]
+    );
+}
+
+#[test]
+fn task_id_and_now_work_in_tasks() {
+    static STACK: Stack<64> = Stack::new();
+    static TASK_LIST: [Task; 2] = [
+        Task::new(first, &STACK).with_name("first"),
+        Task::new(second, &STACK).with_name("second"),
+    ];
+    static SCHEDULER: Scheduler = Scheduler::new(&TASK_LIST);
+    static SEEN: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    fn first() -> ! {
+        loop {
+            SEEN.lock()
+                .unwrap()
+                .push(format!("{}@{}", pets::task_id(), pets::now()));
+            pets::delay(1);
+        }
+    }
+
+    fn second() -> ! {
+        loop {
+            SEEN.lock()
+                .unwrap()
+                .push(format!("{}@{}", pets::task_id(), pets::now()));
+            pets::delay(2);
+        }
+    }
+
+    let sim = Sim::new(&SCHEDULER);
+    sim.advance(2);
+    assert_eq!(
+        *SEEN.lock().unwrap(),
+        ["first@0", "second@0", "first@1", "first@2", "second@2"]
+    );
+}
+
+#[test]
+fn now_precise_counts_timer_counts() {
+    static STACK: Stack<64> = Stack::new();
+    static TASK_LIST: [Task; 1] = [Task::new(sampler, &STACK)];
+    static SCHEDULER: Scheduler = Scheduler::new(&TASK_LIST);
+    static SEEN: Mutex<Vec<u64>> = Mutex::new(Vec::new());
+
+    fn sampler() -> ! {
+        loop {
+            SEEN.lock().unwrap().push(pets::now_precise());
+            pets::delay(2);
+        }
+    }
+
+    // The virtual clock has 1000 counts per tick
+    let sim = Sim::new(&SCHEDULER);
+    sim.advance(4);
+    assert_eq!(*SEEN.lock().unwrap(), [0, 2000, 4000]);
+}
+
+#[cfg(feature = "embedded-hal")]
+#[test]
+fn hal_delays_sleep_between_ticks() {
+    use embedded_hal::delay::DelayNs;
+
+    static STACK: Stack<64> = Stack::new();
+    static TASK_LIST: [Task; 1] = [Task::new(sleeper, &STACK)];
+    static SCHEDULER: Scheduler = Scheduler::new(&TASK_LIST);
+    static SEEN: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+    fn sleeper() -> ! {
+        // The virtual clock has 1000 counts per tick, so this is a 1ms tick
+        let mut delay = pets::Delay::new(1_000_000);
+        loop {
+            SEEN.lock().unwrap().push(pets::now());
+            delay.delay_ms(3);
+            SEEN.lock().unwrap().push(pets::now());
+            // Part of a tick rounds up to a whole tick
+            delay.delay_us(1500);
+        }
+    }
+
+    let sim = Sim::new(&SCHEDULER);
+    sim.advance(10);
+    assert_eq!(*SEEN.lock().unwrap(), [0, 3, 5, 8, 10]);
+}
+
+#[cfg(feature = "embedded-hal")]
+#[test]
+fn async_hal_delays_sleep_between_ticks() {
+    use embedded_hal_async::delay::DelayNs;
+
+    static STACK: Stack<64> = Stack::new();
+    static TASK_LIST: [Task; 1] = [Task::new(sleeper, &STACK)];
+    static SCHEDULER: Scheduler = Scheduler::new(&TASK_LIST);
+    static SEEN: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+    fn sleeper() -> ! {
+        let mut delay = pets::Delay::new(1_000_000);
+        asynch::block_on(async {
+            loop {
+                SEEN.lock().unwrap().push(pets::now());
+                delay.delay_ms(3).await;
+            }
+        })
+    }
+
+    let sim = Sim::new(&SCHEDULER);
+    sim.advance(6);
+    assert_eq!(*SEEN.lock().unwrap(), [0, 3, 6]);
+}
+
+#[test]
+fn task_info_describes_each_task() {
+    static STACK: Stack<64> = Stack::new();
+    static TASK_LIST: [Task; 2] = [
+        Task::new(sleepy, &STACK).with_name("sleepy"),
+        Task::new(sleepy, &STACK),
+    ];
+    static SCHEDULER: Scheduler = Scheduler::new(&TASK_LIST);
+
+    fn sleepy() -> ! {
+        loop {
+            pets::delay(10);
+        }
+    }
+
+    let sim = Sim::new(&SCHEDULER);
+    sim.advance(3);
+    let info: Vec<_> = pets::tasks().collect();
+    assert_eq!(info.len(), 2);
+    assert_eq!(info[0].name, Some("sleepy"));
+    assert_eq!(info[1].name, None);
+    for task in &info {
+        // Tasks run on their thread's stack, so we can't measure their use
+        assert_eq!(task.stack_used, None);
+        assert_eq!(task.stack_size, 64);
+        assert!(task.run_count > 0);
+    }
+    assert!(info[0].to_string().contains(" stack=?/64 "), "{}", info[0]);
+}
+
+#[test]
+fn switches_are_recorded_in_order() {
+    static STACK: Stack<64> = Stack::new();
+    static TASK_LIST: [Task; 3] = [
+        Task::new(every_tick, &STACK),
+        Task::new(every_other_tick, &STACK),
+        Task::new(every_tick, &STACK),
+    ];
+    static SCHEDULER: Scheduler = Scheduler::new(&TASK_LIST);
+
+    fn every_tick() -> ! {
+        loop {
+            pets::delay(1);
+        }
+    }
+
+    fn every_other_tick() -> ! {
+        loop {
+            pets::delay(2);
+        }
+    }
+
+    let sim = Sim::new(&SCHEDULER);
+    let (t0, t1, t2) = (sim.task_id(0), sim.task_id(1), sim.task_id(2));
+    let run = |tick, task| Run { tick, task };
+    assert_eq!(sim.take_history(), [run(0, t0), run(0, t1), run(0, t2)]);
+
+    // Every task is woken on every tick, even if it is only going to check
+    // the time and go back to sleep
+    sim.tick();
+    assert_eq!(sim.take_history(), [run(1, t0), run(1, t1), run(1, t2)]);
+
+    sim.advance(2);
+    assert_eq!(
+        sim.history(),
+        [
+            run(2, t0),
+            run(2, t1),
+            run(2, t2),
+            run(3, t0),
+            run(3, t1),
+            run(3, t2)
+        ]
+    );
+}
+
+#[test]
+fn interrupts_wake_waiting_tasks() {
+    static STACK: Stack<64> = Stack::new();
+    static TASK_LIST: [Task; 1] = [Task::new(handler, &STACK)];
+    static SCHEDULER: Scheduler = Scheduler::new(&TASK_LIST);
+    static PENDING: AtomicBool = AtomicBool::new(false);
+    static HANDLED: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+    fn handler() -> ! {
+        loop {
+            if PENDING.swap(false, Ordering::Relaxed) {
+                HANDLED.lock().unwrap().push(pets::now());
+            }
+            SCHEDULER.yield_until_tick();
+        }
+    }
+
+    let sim = Sim::new(&SCHEDULER);
+    sim.advance(3);
+    assert!(HANDLED.lock().unwrap().is_empty());
+
+    sim.interrupt(|| PENDING.store(true, Ordering::Relaxed));
+    assert_eq!(*HANDLED.lock().unwrap(), [3]);
+    assert_eq!(sim.now(), 3);
+
+    sim.advance(2);
+    sim.interrupt(|| PENDING.store(true, Ordering::Relaxed));
+    assert_eq!(*HANDLED.lock().unwrap(), [3, 5]);
+
+    // The only task never stopped running, so it was only switched in once
+    assert_eq!(
+        sim.history(),
+        [Run {
+            tick: 0,
+            task: sim.task_id(0)
+        }]
+    );
+}
+
+#[test]
+fn async_tasks_wait_for_queues() {
+    static STACK: Stack<64> = Stack::new();
+    static TASK_LIST: [Task; 2] = [Task::new(producer, &STACK), Task::new(consumer, &STACK)];
+    static SCHEDULER: Scheduler = Scheduler::new(&TASK_LIST);
+    static QUEUE: Queue<u32, 2> = Queue::new();
+    static RECEIVED: Mutex<Vec<(u32, u32)>> = Mutex::new(Vec::new());
+
+    fn producer() -> ! {
+        asynch::block_on(async {
+            loop {
+                asynch::delay(2).await;
+                QUEUE.send(pets::now()).await;
+            }
+        })
+    }
+
+    fn consumer() -> ! {
+        asynch::block_on(async {
+            loop {
+                let value = QUEUE.recv().await;
+                RECEIVED.lock().unwrap().push((pets::now(), value));
+            }
+        })
+    }
+
+    let sim = Sim::new(&SCHEDULER);
+    sim.advance(4);
+    assert_eq!(*RECEIVED.lock().unwrap(), [(2, 2), (4, 4)]);
+
+    // An interrupt handler can wake the consumer without waiting for a tick
+    sim.interrupt(|| QUEUE.try_send(100).unwrap());
+    assert_eq!(*RECEIVED.lock().unwrap(), [(2, 2), (4, 4), (4, 100)]);
+}
+
+#[test]
+fn interrupts_release_semaphores() {
+    static STACK: Stack<64> = Stack::new();
+    static TASK_LIST: [Task; 1] = [Task::new(waiter, &STACK)];
+    static SCHEDULER: Scheduler = Scheduler::new(&TASK_LIST);
+    static SEMAPHORE: Semaphore = Semaphore::new(1);
+    static ACQUIRED: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+    fn waiter() -> ! {
+        asynch::block_on(async {
+            loop {
+                SEMAPHORE.acquire().await;
+                ACQUIRED.lock().unwrap().push(pets::now());
+            }
+        })
+    }
+
+    let sim = Sim::new(&SCHEDULER);
+    sim.advance(3);
+    assert_eq!(*ACQUIRED.lock().unwrap(), [0]);
+
+    sim.interrupt(|| SEMAPHORE.release());
+    sim.interrupt(|| SEMAPHORE.release());
+    sim.advance(1);
+    assert_eq!(*ACQUIRED.lock().unwrap(), [0, 3, 3]);
+    assert_eq!(SEMAPHORE.permits(), 0);
+}
+
+#[test]
+fn deferred_work_runs_ahead_of_other_tasks() {
+    static STACK: Stack<64> = Stack::new();
+    static TASK_LIST: [Task; 2] = [Task::new(poster, &STACK), Task::new(worker, &STACK)];
+    static SCHEDULER: Scheduler = Scheduler::new(&TASK_LIST);
+    static DEFERRED: Deferred<2> = Deferred::new();
+    static LOG: Mutex<Vec<(u32, String)>> = Mutex::new(Vec::new());
+
+    fn poster() -> ! {
+        pets::delay(2);
+        DEFERRED.post(work, 1).unwrap();
+        LOG.lock()
+            .unwrap()
+            .push((pets::now(), "posted".to_string()));
+        loop {
+            pets::delay(1);
+        }
+    }
+
+    fn worker() -> ! {
+        DEFERRED.run()
+    }
+
+    fn work(arg: usize) {
+        LOG.lock()
+            .unwrap()
+            .push((pets::now(), format!("work {} in {}", arg, pets::task_id())));
+    }
+
+    let sim = Sim::new(&SCHEDULER);
+    // Work posted before the worker starts waits for it
+    sim.interrupt(|| DEFERRED.post(work, 0).unwrap());
+    sim.advance(1);
+    assert_eq!(*LOG.lock().unwrap(), [(0, "work 0 in T001".to_string())]);
+
+    // A task that posts work is pre-empted by the worker
+    sim.advance(1);
+    assert_eq!(
+        LOG.lock().unwrap()[1..],
+        [(2, "work 1 in T001".to_string()), (2, "posted".to_string())]
+    );
+
+    // An interrupt handler's work runs without waiting for a tick
+    sim.interrupt(|| {
+        DEFERRED.post(work, 2).unwrap();
+        DEFERRED.post(work, 3).unwrap();
+        assert_eq!(DEFERRED.post(work, 4), Err(pets::DeferredFull));
+    });
+    assert_eq!(
+        LOG.lock().unwrap()[3..],
+        [
+            (2, "work 2 in T001".to_string()),
+            (2, "work 3 in T001".to_string())
+        ]
+    );
+    assert!(DEFERRED.is_empty());
+}
+
+#[test]
+#[cfg(feature = "trace")]
+fn wakes_whilst_parking_are_not_lost() {
+    static STACK: Stack<64> = Stack::new();
+    static TASK_LIST: [Task; 1] = [Task::new(worker, &STACK)];
+    static SCHEDULER: Scheduler = Scheduler::new(&TASK_LIST);
+    static DEFERRED: Deferred<4> = Deferred::new();
+    static WORKER_THREAD: Mutex<Option<std::thread::ThreadId>> = Mutex::new(None);
+    static ARMED: AtomicBool = AtomicBool::new(false);
+    static DONE: Mutex<Vec<(u32, usize)>> = Mutex::new(Vec::new());
+
+    fn worker() -> ! {
+        *WORKER_THREAD.lock().unwrap() = Some(std::thread::current().id());
+        DEFERRED.run()
+    }
+
+    fn work(arg: usize) {
+        DONE.lock().unwrap().push((pets::now(), arg));
+    }
+
+    // Post work just after the worker parks, but before it switches away,
+    // like an interrupt would. Trace hooks are global, so ignore other
+    // tests' tasks.
+    fn on_event(event: pets::TraceEvent, _task_id: TaskId, _timestamp: u32) {
+        if event == pets::TraceEvent::Park
+            && *WORKER_THREAD.lock().unwrap() == Some(std::thread::current().id())
+            && ARMED.swap(false, Ordering::Relaxed)
+        {
+            DEFERRED.post(work, 42).unwrap();
+        }
+    }
+
+    pets::set_trace_hook(on_event);
+    let sim = Sim::new(&SCHEDULER);
+    ARMED.store(true, Ordering::Relaxed);
+    sim.advance(1);
+    assert!(!ARMED.load(Ordering::Relaxed));
+    // The worker saw the work before it went to sleep
+    assert_eq!(*DONE.lock().unwrap(), [(1, 42)]);
+}
+
+#[test]
+#[cfg(feature = "edf")]
+fn earliest_deadline_runs_first() {
+    static STACK: Stack<64> = Stack::new();
+    static TASK_LIST: [Task; 3] = [
+        Task::new(background, &STACK),
+        Task::new(slow, &STACK).with_deadline(8, 8),
+        Task::new(fast, &STACK).with_deadline(4, 2),
+    ];
+    static SCHEDULER: Scheduler<pets::EarliestDeadlineFirst> =
+        Scheduler::new(&TASK_LIST).with_policy(pets::EarliestDeadlineFirst);
+    static LOG: Mutex<Vec<(u32, &str)>> = Mutex::new(Vec::new());
+
+    fn background() -> ! {
+        loop {
+            LOG.lock().unwrap().push((pets::now(), "background"));
+            pets::delay(4);
+        }
+    }
+
+    fn slow() -> ! {
+        loop {
+            LOG.lock().unwrap().push((pets::now(), "slow"));
+            // Overrun, so we miss our deadline
+            pets::delay(10);
+            pets::wait_for_next_period();
+        }
+    }
+
+    fn fast() -> ! {
+        loop {
+            LOG.lock().unwrap().push((pets::now(), "fast"));
+            pets::wait_for_next_period();
+        }
+    }
+
+    let sim = Sim::new(&SCHEDULER);
+    sim.advance(16);
+    assert_eq!(
+        *LOG.lock().unwrap(),
+        [
+            (0, "background"),
+            (0, "fast"),
+            (0, "slow"),
+            (4, "fast"),
+            (4, "background"),
+            (8, "fast"),
+            (8, "background"),
+            (10, "slow"),
+            (12, "fast"),
+            (12, "background"),
+            (16, "fast"),
+            (16, "background"),
+        ]
+    );
+    let misses: Vec<u32> = pets::tasks().map(|info| info.deadline_misses).collect();
+    assert_eq!(misses, [0, 2, 0]);
+}
+
+#[test]
+#[cfg(feature = "edf")]
+fn unreleased_jobs_wait_however_early_their_deadline() {
+    static STACK: Stack<64> = Stack::new();
+    static TASK_LIST: [Task; 2] = [
+        Task::new(urgent, &STACK).with_deadline(10, 2),
+        Task::new(long, &STACK).with_deadline(20, 20),
+    ];
+    static SCHEDULER: Scheduler<pets::EarliestDeadlineFirst> =
+        Scheduler::new(&TASK_LIST).with_policy(pets::EarliestDeadlineFirst);
+    static JOBS: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+    fn urgent() -> ! {
+        loop {
+            JOBS.lock().unwrap().push(pets::now());
+            pets::wait_for_next_period();
+        }
+    }
+
+    fn long() -> ! {
+        loop {
+            sim::spin(6);
+            pets::wait_for_next_period();
+        }
+    }
+
+    let sim = Sim::new(&SCHEDULER);
+    sim.take_history();
+    // The urgent task's next deadline (tick 12) is earlier than the long
+    // task's (tick 20), but its job isn't released until tick 10, so it
+    // mustn't interrupt the long task
+    sim.advance(5);
+    assert_eq!(sim.take_history(), []);
+    sim.advance(6);
+    assert_eq!(*JOBS.lock().unwrap(), [0, 10]);
+    let misses: Vec<u32> = pets::tasks().map(|info| info.deadline_misses).collect();
+    assert_eq!(misses, [0, 0]);
+}
+
+#[test]
+#[cfg(feature = "edf")]
+fn policies_see_deadlines_and_time_slices() {
+    use pets::{RoundRobin, SchedPolicy, SchedView, TaskSelection};
+
+    /// Takes turns like [`RoundRobin`], and notes what it sees on each tick
+    struct Watcher;
+
+    impl SchedPolicy for Watcher {
+        fn select(&self, view: &SchedView) -> TaskSelection {
+            RoundRobin.select(view)
+        }
+
+        fn preempt(&self, view: &SchedView) -> bool {
+            let task = &view.tasks()[view.current_task().index()];
+            SEEN.lock()
+                .unwrap()
+                .push((view.now(), view.slice_left(), task.job_deadline()));
+            false
+        }
+    }
+
+    static STACK: Stack<64> = Stack::new();
+    static TASK_LIST: [Task; 2] = [
+        Task::new(worker, &STACK)
+            .with_quantum(3)
+            .with_deadline(10, 8),
+        Task::new(worker, &STACK),
+    ];
+    static SCHEDULER: Scheduler<Watcher> = Scheduler::new(&TASK_LIST).with_policy(Watcher);
+    static SEEN: Mutex<Vec<(u32, u32, Option<u32>)>> = Mutex::new(Vec::new());
+
+    fn worker() -> ! {
+        loop {
+            sim::spin(1);
+        }
+    }
+
+    let sim = Sim::new(&SCHEDULER);
+    sim.advance(5);
+    assert_eq!(
+        *SEEN.lock().unwrap(),
+        [
+            (1, 3, Some(8)),
+            (2, 2, Some(8)),
+            (3, 1, Some(8)),
+            (4, 1, None),
+            (5, 3, Some(8)),
+        ]
+    );
+}
+
+#[test]
+fn cpu_usage_slides_along_every_tick() {
+    static STACK: Stack<64> = Stack::new();
+    static TASK_LIST: [Task; 2] = [Task::new(busy, &STACK), Task::new(lazy, &STACK)];
+    static SCHEDULER: Scheduler = Scheduler::new(&TASK_LIST);
+    static STOP: AtomicBool = AtomicBool::new(false);
+
+    fn busy() -> ! {
+        while !STOP.load(Ordering::Relaxed) {
+            sim::spin(1);
+        }
+        loop {
+            pets::delay(1000);
+        }
+    }
+
+    fn lazy() -> ! {
+        loop {
+            pets::delay(1);
+        }
+    }
+
+    let percent = |task: usize| {
+        SCHEDULER
+            .cpu_usage::<2>()
+            .task_percent(TaskId::new(task))
+            .unwrap()
+    };
+    let sim = Sim::new(&SCHEDULER);
+    sim.advance(Scheduler::USAGE_WINDOW_TICKS * 4);
+    assert!(percent(0) >= 95, "busy task only got {}%", percent(0));
+    assert_eq!(percent(1), 0);
+    // Tasks we have no room for are left out, but their time still counts
+    let usage = SCHEDULER.cpu_usage::<1>();
+    assert_eq!(usage.iter().count(), 1);
+    assert_eq!(usage.task_percent(TaskId::new(1)), None);
+    assert_eq!(usage.task_percent(TaskId::new(0)), Some(percent(0)));
+    assert_eq!(SCHEDULER.cpu_usage::<2>().idle_percent(), 0);
+
+    // The idle time shows up straight away, without waiting for a new window
+    STOP.store(true, Ordering::Relaxed);
+    sim.advance(4);
+    let idle = SCHEDULER.cpu_usage::<2>().idle_percent();
+    assert!((5..50).contains(&idle), "idle was {}% after 4 ticks", idle);
+
+    // ...and the busy time fades away
+    sim.advance(Scheduler::USAGE_WINDOW_TICKS * 4);
+    assert!(percent(0) <= 5, "busy task still had {}%", percent(0));
+    assert!(SCHEDULER.cpu_usage::<2>().idle_percent() >= 95);
+}
+
+#[test]
+#[should_panic(expected = "task failed")]
+fn task_panics_reach_the_test() {
+    static STACK: Stack<64> = Stack::new();
+    static TASK_LIST: [Task; 1] = [Task::new(doomed, &STACK)];
+    static SCHEDULER: Scheduler = Scheduler::new(&TASK_LIST);
+
+    fn doomed() -> ! {
+        pets::delay(3);
+        panic!("task failed");
+    }
+
+    let sim = Sim::new(&SCHEDULER);
+    sim.advance(5);
+}
+
+#[test]
+#[should_panic(expected = "Only tasks can wait")]
+fn interrupts_cannot_wait() {
+    static STACK: Stack<64> = Stack::new();
+    static TASK_LIST: [Task; 1] = [Task::new(idler, &STACK)];
+    static SCHEDULER: Scheduler = Scheduler::new(&TASK_LIST);
+
+    fn idler() -> ! {
+        loop {
+            pets::delay(1);
+        }
+    }
+
+    let sim = Sim::new(&SCHEDULER);
+    sim.interrupt(|| pets::delay(1));
+}
+
+// End of File
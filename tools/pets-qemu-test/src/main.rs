@@ -0,0 +1,502 @@
+//! Runs the pets `ci-check` example in QEMU, and checks how it behaved
+//!
+//! We build the example for the given target, boot it in QEMU,
+//! decode the log with `defmt-print`, and then check things like "the cat
+//! task ran every 3 ticks" and "no task was starved". Unlike comparing the
+//! whole log against a reference copy, this doesn't break whenever some code
+//! moves, or a log message changes.
+//!
+//! ```bash
+//! pets-qemu-test --target thumbv7em-none-eabi
+//! ```
+//!
+//! Add `--cpu cortex-m55` (with `--target thumbv8m.main-none-eabihf`) to
+//! build for Armv8.1-M and run on an MPS3 AN547 instead. Add `--trustzone`
+//! (with an Armv8-M Mainline target) to run the example in the Non-secure
+//! world of the MPS2 AN505.
+//!
+//! You need `qemu-system-arm` (or `qemu-system-riscv32` for RISC-V) and
+//! `defmt-print` on your `PATH`. The exit
+//! status is non-zero if anything went wrong.
+
+// Copyright (c) 2025 Ferrous Systems
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    error::Error,
+    io::BufRead,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+/// The example we run
+const BINARY: &str = "ci-check";
+
+/// How long the example may take, before we decide it has hung
+///
+/// This is much longer than it should take, but CI machines can be slow.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The tasks in the example, and how often each one should log, in ticks
+const TASKS: &[(&str, u32)] = &[("rabbit", 5), ("hamster", 10), ("cat", 3)];
+
+/// The extra tasks the example has with TrustZone, which only log at debug
+/// level, at no fixed rate
+const TRUSTZONE_TASKS: &[&str] = &["hedgehog", "tortoise", "owl"];
+
+/// How many times the hamster task logs before it exits
+const HAMSTER_LOOPS: usize = 5;
+
+/// A line of log output, as formatted by `defmt-print`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct LogLine {
+    /// The scheduler tick count when the line was logged
+    tick: u32,
+    /// The name of the task which logged the line
+    task: String,
+    /// The log level, or an empty string for `println!`
+    level: String,
+    /// The message itself
+    message: String,
+}
+
+impl LogLine {
+    /// The format we ask `defmt-print` for, which [`LogLine::parse`] expects
+    ///
+    /// The examples set the timestamp to be the tick count, the time in timer
+    /// counts, and the task.
+    const FORMAT: &str = "{t} {L} | {s}";
+
+    /// Try and parse a line from `defmt-print`
+    fn parse(line: &str) -> Option<LogLine> {
+        let (header, message) = line.split_once(" | ")?;
+        let mut fields = header.split_whitespace();
+        let tick = fields.next()?.parse().ok()?;
+        let _counts: u64 = fields.next()?.parse().ok()?;
+        let task = fields.next()?.to_string();
+        let level = fields.next().unwrap_or_default().to_string();
+        Some(LogLine {
+            tick,
+            task,
+            level,
+            message: message.to_string(),
+        })
+    }
+}
+
+/// How to build and run the example for a given target
+struct Machine {
+    /// The examples package, relative to the root of the repository
+    examples: &'static str,
+    /// The QEMU binary to run
+    qemu: &'static str,
+    /// The arguments which select the QEMU machine
+    args: &'static [&'static str],
+    /// The CPU to build for, if not the target's default
+    cpu: Option<&'static str>,
+    /// The cargo features to build the example with, if any
+    features: Option<&'static str>,
+}
+
+impl Machine {
+    /// Find a QEMU machine which can run the given target and CPU
+    fn for_target(target: &str, cpu: Option<&str>, trustzone: bool) -> Result<Machine, String> {
+        if trustzone {
+            return match cpu {
+                None if target.starts_with("thumbv8m.main") => Ok(Machine {
+                    examples: "examples",
+                    qemu: "qemu-system-arm",
+                    args: &["-cpu", "cortex-m33", "-machine", "mps2-an505"],
+                    cpu: None,
+                    features: Some("trustzone"),
+                }),
+                _ => Err(format!(
+                    "Don't know how to run {:?} on {:?} with TrustZone in QEMU",
+                    target, cpu
+                )),
+            };
+        }
+        if let Some(cpu) = cpu {
+            return match cpu {
+                "cortex-m55" if target.starts_with("thumbv8m.main") => Ok(Machine {
+                    examples: "examples",
+                    qemu: "qemu-system-arm",
+                    args: &["-cpu", "cortex-m55", "-machine", "mps3-an547"],
+                    cpu: Some("cortex-m55"),
+                    features: None,
+                }),
+                _ => Err(format!(
+                    "Don't know how to run {:?} on {:?} in QEMU",
+                    target, cpu
+                )),
+            };
+        }
+        if target.starts_with("thumbv8m") {
+            Ok(Machine {
+                examples: "examples",
+                qemu: "qemu-system-arm",
+                args: &["-cpu", "cortex-m33", "-machine", "mps2-an505"],
+                cpu: None,
+                features: None,
+            })
+        } else if target.starts_with("thumbv6m") || target.starts_with("thumbv7") {
+            Ok(Machine {
+                examples: "examples",
+                qemu: "qemu-system-arm",
+                args: &["-cpu", "cortex-m4", "-machine", "mps2-an386"],
+                cpu: None,
+                features: None,
+            })
+        } else if target.starts_with("armv8r") {
+            Ok(Machine {
+                examples: "examples-cortex-r",
+                qemu: "qemu-system-arm",
+                args: &["-machine", "mps3-an536"],
+                cpu: None,
+                features: None,
+            })
+        } else if target.starts_with("riscv32") {
+            Ok(Machine {
+                examples: "examples-riscv",
+                qemu: "qemu-system-riscv32",
+                args: &["-machine", "virt", "-bios", "none"],
+                cpu: None,
+                features: None,
+            })
+        } else {
+            Err(format!("Don't know how to run {:?} in QEMU", target))
+        }
+    }
+}
+
+/// Build the example, and return the path to the ELF file
+fn build(examples_dir: &Path, target: &str, machine: &Machine) -> Result<PathBuf, Box<dyn Error>> {
+    let mut command = Command::new(std::env::var("CARGO").unwrap_or_else(|_| "cargo".into()));
+    command.current_dir(examples_dir).args([
+        "build",
+        "--release",
+        "--target",
+        target,
+        "--bin",
+        BINARY,
+    ]);
+    if let Some(features) = machine.features {
+        command.args(["--features", features]);
+    }
+    if let Some(cpu) = machine.cpu {
+        // This adds to the rustflags in the examples' own config
+        command.arg("--config").arg(format!(
+            "target.{}.rustflags=[\"-Ctarget-cpu={}\"]",
+            target, cpu
+        ));
+    }
+    let status = command.status()?;
+    if !status.success() {
+        return Err(format!("Building {} failed: {}", BINARY, status).into());
+    }
+    Ok(examples_dir
+        .join("target")
+        .join(target)
+        .join("release")
+        .join(BINARY))
+}
+
+/// Run the example in QEMU, and return the decoded log
+///
+/// The example uses semihosting to tell QEMU what exit status to use, so a
+/// failing exit status means the example thinks something went wrong.
+fn run(elf: &Path, machine: &Machine) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut qemu = Command::new(machine.qemu)
+        .args(machine.args)
+        .args([
+            "-semihosting-config",
+            "enable=on,target=native",
+            "-nographic",
+        ])
+        .arg("-kernel")
+        .arg(elf)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut decoder = Command::new("defmt-print")
+        .arg("-e")
+        .arg(elf)
+        .args(["--log-format", LogLine::FORMAT])
+        .stdin(qemu.stdout.take().ok_or("no stdout from QEMU")?)
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    // Read the log on another thread, so we can watch the clock on this one
+    let decoded = decoder.stdout.take().ok_or("no stdout from defmt-print")?;
+    let (tx, rx) = mpsc::channel();
+    let reader = std::thread::spawn(move || {
+        for line in std::io::BufReader::new(decoded).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            println!("{}", line);
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let deadline = Instant::now() + TIMEOUT;
+    let status = loop {
+        if let Some(status) = qemu.try_wait()? {
+            break status;
+        }
+        if Instant::now() > deadline {
+            qemu.kill()?;
+            let _ = decoder.kill();
+            return Err(format!("{} didn't exit within {:?}", BINARY, TIMEOUT).into());
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    decoder.wait()?;
+    let _ = reader.join();
+    if !status.success() {
+        return Err(format!("QEMU exited with {}", status).into());
+    }
+    Ok(rx.try_iter().collect())
+}
+
+/// Check the log shows the example behaved itself
+///
+/// With `trustzone`, the TrustZone tasks must have logged too. Returns a list
+/// of everything that was wrong.
+fn check(log: &[LogLine], trustzone: bool) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    for line in log {
+        if line.level == "WARN" || line.level == "ERROR" {
+            problems.push(format!("Unexpected {} log: {:?}", line.level, line));
+        }
+        if line.message.starts_with("PANIC") || line.message.starts_with("FAULT") {
+            problems.push(format!("Crashed: {:?}", line));
+        }
+    }
+
+    let Some(last_tick) = log.iter().map(|line| line.tick).max() else {
+        problems.push("The log was empty".to_string());
+        return problems;
+    };
+
+    for &(task, period) in TASKS {
+        let ticks: Vec<u32> = log
+            .iter()
+            .filter(|line| line.task == task && line.level == "INFO")
+            .map(|line| line.tick)
+            .collect();
+        // Each task logs straight away, then every `period` ticks, exactly
+        for (idx, &tick) in ticks.iter().enumerate() {
+            let expected = idx as u32 * period;
+            if tick != expected {
+                problems.push(format!(
+                    "Task {} logged at tick {}, but expected tick {}",
+                    task, tick, expected
+                ));
+                break;
+            }
+        }
+        // No task should go quiet whilst the others carry on
+        match ticks.last() {
+            Some(&tick) if last_tick - tick <= period => {}
+            Some(&tick) => problems.push(format!(
+                "Task {} starved: last logged at tick {}, but the log ran until tick {}",
+                task, tick, last_tick
+            )),
+            None => problems.push(format!("Task {} never ran", task)),
+        }
+    }
+
+    let hamster_loops = log
+        .iter()
+        .filter(|line| line.task == "hamster" && line.level == "INFO")
+        .count();
+    if hamster_loops != HAMSTER_LOOPS {
+        problems.push(format!(
+            "Hamster logged {} times, but expected {}",
+            hamster_loops, HAMSTER_LOOPS
+        ));
+    }
+
+    if trustzone {
+        for &task in TRUSTZONE_TASKS {
+            if !log.iter().any(|line| line.task == task) {
+                problems.push(format!("Task {} never ran", task));
+            }
+        }
+    }
+
+    problems
+}
+
+/// Entry point to the tool
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut target = None;
+    let mut cpu = None;
+    let mut trustzone = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--trustzone" {
+            trustzone = true;
+            continue;
+        }
+        // Accept both `--flag value` and `--flag=value`
+        let (flag, value) = match arg.split_once('=') {
+            Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+            None => (arg.clone(), None),
+        };
+        let slot = match flag.as_str() {
+            "--target" => &mut target,
+            "--cpu" => &mut cpu,
+            _ => {
+                eprintln!("Usage: pets-qemu-test --target <TARGET> [--cpu <CPU>] [--trustzone]");
+                return Err(format!("Unknown argument {:?}", arg).into());
+            }
+        };
+        *slot = Some(
+            value
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("{} needs a value", flag))?,
+        );
+    }
+    let target = target.ok_or("--target is required")?;
+
+    let machine = Machine::for_target(&target, cpu.as_deref(), trustzone)?;
+    let examples_dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("../..")
+        .join(machine.examples);
+    let elf = build(&examples_dir, &target, &machine)?;
+    let log: Vec<LogLine> = run(&elf, &machine)?
+        .iter()
+        .filter_map(|line| LogLine::parse(line))
+        .collect();
+
+    let problems = check(&log, trustzone);
+    if problems.is_empty() {
+        println!("{} on {} behaved correctly", BINARY, target);
+        Ok(())
+    } else {
+        for problem in &problems {
+            eprintln!("{}", problem);
+        }
+        Err(format!("{} problem(s) found", problems.len()).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Make a log line
+    fn line(tick: u32, task: &str, level: &str, message: &str) -> LogLine {
+        LogLine {
+            tick,
+            task: task.to_string(),
+            level: level.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    /// Make the log of a run which went to plan, up to the given tick
+    fn good_log(last_tick: u32) -> Vec<LogLine> {
+        let mut log = Vec::new();
+        for tick in 0..=last_tick {
+            for &(task, period) in TASKS {
+                if tick % period == 0 {
+                    log.push(line(tick, task, "INFO", "Hello"));
+                }
+            }
+        }
+        log
+    }
+
+    #[test]
+    fn lines_are_parsed() {
+        assert_eq!(
+            LogLine::parse("12 300000 cat INFO  | Cat! (back in 3)"),
+            Some(line(12, "cat", "INFO", "Cat! (back in 3)"))
+        );
+        assert_eq!(
+            LogLine::parse("7 175000 rabbit | Hello"),
+            Some(line(7, "rabbit", "", "Hello"))
+        );
+        assert_eq!(LogLine::parse("Booting..."), None);
+        assert_eq!(LogLine::parse("x 175000 rabbit | Hello"), None);
+    }
+
+    #[test]
+    fn good_runs_pass() {
+        assert_eq!(check(&good_log(40), false), Vec::<String>::new());
+    }
+
+    #[test]
+    fn empty_logs_fail() {
+        assert_eq!(check(&[], false), ["The log was empty"]);
+    }
+
+    #[test]
+    fn starved_tasks_are_reported() {
+        let mut log = good_log(40);
+        log.retain(|line| line.task != "cat" || line.tick <= 18);
+        assert_eq!(
+            check(&log, false),
+            ["Task cat starved: last logged at tick 18, but the log ran until tick 40"]
+        );
+    }
+
+    #[test]
+    fn logs_a_tick_late_are_reported() {
+        let mut log = good_log(40);
+        for line in log.iter_mut().filter(|line| line.task == "rabbit") {
+            line.tick += 1;
+        }
+        assert_eq!(
+            check(&log, false),
+            ["Task rabbit logged at tick 1, but expected tick 0"]
+        );
+    }
+
+    #[test]
+    fn crashes_are_reported() {
+        let mut log = good_log(40);
+        log.push(line(40, "cat", "", "PANIC at src/main.rs:10"));
+        let problems = check(&log, false);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("Crashed: "), "{:?}", problems);
+
+        log.push(line(40, "cat", "WARN", "Uh-oh"));
+        assert_eq!(check(&log, false).len(), 2);
+    }
+
+    #[test]
+    fn hamster_must_loop_the_right_number_of_times() {
+        assert_eq!(
+            check(&good_log(50), false),
+            ["Hamster logged 6 times, but expected 5"]
+        );
+    }
+
+    #[test]
+    fn trustzone_tasks_must_run() {
+        let mut log = good_log(40);
+        assert_eq!(
+            check(&log, true),
+            [
+                "Task hedgehog never ran",
+                "Task tortoise never ran",
+                "Task owl never ran"
+            ]
+        );
+        for &task in TRUSTZONE_TASKS {
+            log.push(line(20, task, "DEBUG", "Hello"));
+        }
+        assert_eq!(check(&log, true), Vec::<String>::new());
+    }
+}
+
+// End of File
@@ -0,0 +1,405 @@
+//! Converts a pets scheduler trace into a Chrome trace-event JSON file
+//!
+//! Build your firmware with the pets `trace` feature, and install
+//! `pets::defmt_trace_hook` with `pets::set_trace_hook`. Then pipe the
+//! decoded defmt output (e.g. from `defmt-print`) into this tool:
+//!
+//! ```bash
+//! pets-trace --systick-hz 25000000 < log.txt > trace.json
+//! ```
+//!
+//! Open `trace.json` in <https://ui.perfetto.dev> or `chrome://tracing` to see
+//! which task was running when. Tasks are labelled with their names, if they
+//! have them. Any lines which are not trace events are ignored.
+
+// Copyright (c) 2025 Ferrous Systems
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    error::Error,
+    io::{BufRead, Write as _},
+};
+
+/// The marker that `pets::defmt_trace_hook` puts in front of every event
+const MARKER: &str = "PETS-TRACE ";
+
+/// The SysTick frequency of the QEMU MPS2 machines the examples run on
+const DEFAULT_SYSTICK_HZ: u64 = 25_000_000;
+
+/// The task ID pets uses when no task is running
+const INVALID_TASK: usize = u32::MAX as usize;
+
+/// The kinds of event pets can record
+///
+/// These must match `pets::TraceEvent`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Event {
+    /// The task is about to start running
+    SwitchIn,
+    /// The task has stopped running
+    SwitchOut,
+    /// The task has nothing to do until the next tick
+    Park,
+    /// The task is runnable again
+    Unpark,
+    /// The scheduler ticked, whilst the task was running
+    Tick,
+}
+
+impl Event {
+    /// Convert from the numeric value of `pets::TraceEvent`
+    fn from_u8(value: u8) -> Option<Event> {
+        match value {
+            0 => Some(Event::SwitchIn),
+            1 => Some(Event::SwitchOut),
+            2 => Some(Event::Park),
+            3 => Some(Event::Unpark),
+            4 => Some(Event::Tick),
+            _ => None,
+        }
+    }
+
+    /// A name for this event in the timeline
+    fn name(self) -> &'static str {
+        match self {
+            Event::SwitchIn => "switch-in",
+            Event::SwitchOut => "switch-out",
+            Event::Park => "park",
+            Event::Unpark => "unpark",
+            Event::Tick => "tick",
+        }
+    }
+}
+
+/// One trace event, as read from the log
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Record {
+    /// What happened
+    event: Event,
+    /// Which task it happened to, if any
+    task: Option<usize>,
+    /// When it happened, in (wrapping) SysTick counts
+    timestamp: u32,
+    /// The name of the task, which only comes with some events
+    name: Option<String>,
+}
+
+impl Record {
+    /// Try and find a trace event in a line of decoded defmt output
+    ///
+    /// The name, if there is one, is the rest of the line, so it can have
+    /// spaces in.
+    fn parse(line: &str) -> Option<Record> {
+        let (_, rest) = line.split_once(MARKER)?;
+        let (event, rest) = split_field(rest)?;
+        let event = Event::from_u8(event.parse().ok()?)?;
+        let (task, rest) = split_field(rest)?;
+        let task: usize = task.parse().ok()?;
+        let (timestamp, rest) = split_field(rest)?;
+        let timestamp = timestamp.parse().ok()?;
+        let name = rest.trim();
+        let name = (!name.is_empty()).then(|| name.to_string());
+        Some(Record {
+            event,
+            task: (task != INVALID_TASK).then_some(task),
+            timestamp,
+            name,
+        })
+    }
+}
+
+/// Split the first whitespace-separated field off some text
+///
+/// Returns `None` if there are no fields left.
+fn split_field(text: &str) -> Option<(&str, &str)> {
+    let text = text.trim_start();
+    if text.is_empty() {
+        return None;
+    }
+    Some(text.split_once(char::is_whitespace).unwrap_or((text, "")))
+}
+
+/// Escape some text so it can go between the quotes of a JSON string
+fn json_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if u32::from(c) < 0x20 => escaped.push_str(&format!("\\u{:04x}", u32::from(c))),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Turns a stream of [`Record`]s into Chrome trace events
+struct Converter {
+    /// How many SysTick counts there are per second
+    systick_hz: u64,
+    /// The previous timestamp, unwrapped, so we can spot it wrapping
+    last_counts: Option<i64>,
+    /// The latest timestamp so far, unwrapped
+    end_counts: i64,
+    /// When each currently running task was switched in, in microseconds
+    running_since: BTreeMap<usize, f64>,
+    /// All the tasks we have seen
+    tasks: BTreeSet<usize>,
+    /// The names of the tasks which have them
+    names: BTreeMap<usize, String>,
+    /// The JSON objects we have produced so far
+    events: Vec<String>,
+}
+
+impl Converter {
+    /// Create a new, empty, converter
+    fn new(systick_hz: u64) -> Converter {
+        Converter {
+            systick_hz,
+            last_counts: None,
+            end_counts: 0,
+            running_since: BTreeMap::new(),
+            tasks: BTreeSet::new(),
+            names: BTreeMap::new(),
+            events: Vec::new(),
+        }
+    }
+
+    /// Convert a raw timestamp to microseconds, handling wrap-around
+    ///
+    /// Events can arrive slightly out of order (e.g. when an interrupt logs
+    /// whilst a task is part way through logging), so we take whichever way
+    /// round the 32-bit counter is the shorter step from the last timestamp.
+    /// Only a step of more than 2^31 counts backwards is a wrap.
+    fn micros(&mut self, timestamp: u32) -> f64 {
+        let counts = match self.last_counts {
+            Some(last) => {
+                let step = timestamp.wrapping_sub(last as u32) as i32;
+                last + i64::from(step)
+            }
+            None => i64::from(timestamp),
+        };
+        self.last_counts = Some(counts);
+        self.end_counts = self.end_counts.max(counts);
+        self.counts_to_micros(counts)
+    }
+
+    /// Convert an unwrapped timestamp to microseconds
+    fn counts_to_micros(&self, counts: i64) -> f64 {
+        (counts as f64 * 1_000_000.0) / self.systick_hz as f64
+    }
+
+    /// Get the label for a task in the timeline
+    fn task_name(&self, task: usize) -> String {
+        match self.names.get(&task) {
+            Some(name) => name.clone(),
+            None => format!("T{:03}", task),
+        }
+    }
+
+    /// Process one record
+    fn push(&mut self, record: Record) {
+        let ts = self.micros(record.timestamp);
+        let Some(task) = record.task else {
+            // Ticks whilst nothing is running yet aren't very interesting
+            return;
+        };
+        self.tasks.insert(task);
+        if let Some(name) = record.name {
+            self.names.insert(task, name);
+        }
+        match record.event {
+            Event::SwitchIn => {
+                self.running_since.insert(task, ts);
+            }
+            Event::SwitchOut => {
+                if let Some(start) = self.running_since.remove(&task) {
+                    self.push_slice(task, start, ts);
+                }
+            }
+            Event::Park | Event::Unpark | Event::Tick => {
+                self.events.push(format!(
+                    r#"{{"name":"{}","ph":"i","s":"t","pid":1,"tid":{},"ts":{:.3}}}"#,
+                    record.event.name(),
+                    task,
+                    ts
+                ));
+            }
+        }
+    }
+
+    /// Record that a task ran between the two given times
+    fn push_slice(&mut self, task: usize, start: f64, end: f64) {
+        self.events.push(format!(
+            r#"{{"name":"{}","ph":"X","pid":1,"tid":{},"ts":{:.3},"dur":{:.3}}}"#,
+            json_escape(&self.task_name(task)),
+            task,
+            start,
+            end - start
+        ));
+    }
+
+    /// Close off any running tasks and produce the JSON document
+    fn finish(mut self) -> String {
+        if self.last_counts.is_some() {
+            let end = self.counts_to_micros(self.end_counts);
+            for (task, start) in core::mem::take(&mut self.running_since) {
+                self.push_slice(task, start, end);
+            }
+        }
+        let mut objects =
+            vec![r#"{"name":"process_name","ph":"M","pid":1,"args":{"name":"pets"}}"#.to_string()];
+        for task in self.tasks.iter() {
+            objects.push(format!(
+                r#"{{"name":"thread_name","ph":"M","pid":1,"tid":{},"args":{{"name":"{}"}}}}"#,
+                task,
+                json_escape(&self.task_name(*task))
+            ));
+        }
+        objects.append(&mut self.events);
+        let mut output = String::from("{\"traceEvents\":[\n");
+        output.push_str(&objects.join(",\n"));
+        output.push_str("\n],\"displayTimeUnit\":\"ms\"}\n");
+        output
+    }
+}
+
+/// Entry point to the tool
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut systick_hz = DEFAULT_SYSTICK_HZ;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--systick-hz" => {
+                systick_hz = args.next().ok_or("--systick-hz needs a value")?.parse()?;
+            }
+            _ => {
+                eprintln!("Usage: pets-trace [--systick-hz <HZ>] < log.txt > trace.json");
+                return Err(format!("Unknown argument {:?}", arg).into());
+            }
+        }
+    }
+
+    let mut converter = Converter::new(systick_hz);
+    for line in std::io::stdin().lock().lines() {
+        if let Some(record) = Record::parse(&line?) {
+            converter.push(record);
+        }
+    }
+    std::io::stdout().write_all(converter.finish().as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run some log lines through a converter, and return it
+    fn convert(lines: &[&str]) -> Converter {
+        let mut converter = Converter::new(1_000_000);
+        for line in lines {
+            if let Some(record) = Record::parse(line) {
+                converter.push(record);
+            }
+        }
+        converter
+    }
+
+    #[test]
+    fn records_are_parsed() {
+        assert_eq!(
+            Record::parse("0000001234 000000030852 T--- PETS-TRACE 4 4294967295 100"),
+            Some(Record {
+                event: Event::Tick,
+                task: None,
+                timestamp: 100,
+                name: None,
+            })
+        );
+        assert_eq!(
+            Record::parse("PETS-TRACE 0 2 4000000000 cat"),
+            Some(Record {
+                event: Event::SwitchIn,
+                task: Some(2),
+                timestamp: 4_000_000_000,
+                name: Some("cat".to_string()),
+            })
+        );
+        assert_eq!(Record::parse("INFO Cat! (back in 3)"), None);
+        assert_eq!(Record::parse("PETS-TRACE 9 2 100"), None);
+        assert_eq!(Record::parse("PETS-TRACE 0 2"), None);
+    }
+
+    #[test]
+    fn timestamps_wrap_around() {
+        let mut converter = Converter::new(1_000_000);
+        assert_eq!(converter.micros(u32::MAX - 9), f64::from(u32::MAX - 9));
+        assert_eq!(converter.micros(10), (1u64 << 32) as f64 + 10.0);
+        assert_eq!(converter.micros(20), (1u64 << 32) as f64 + 20.0);
+    }
+
+    #[test]
+    fn reordered_timestamps_are_not_wraps() {
+        let mut converter = Converter::new(1_000_000);
+        assert_eq!(converter.micros(1000), 1000.0);
+        assert_eq!(converter.micros(990), 990.0);
+        assert_eq!(converter.micros(1010), 1010.0);
+        assert_eq!(converter.micros(2_000_000_000), 2_000_000_000.0);
+        assert_eq!(converter.micros(4_000_000_000), 4_000_000_000.0);
+        // Out of order across a wrap, in both directions
+        assert_eq!(converter.micros(u32::MAX), f64::from(u32::MAX));
+        assert_eq!(converter.micros(5), (1u64 << 32) as f64 + 5.0);
+        assert_eq!(converter.micros(u32::MAX - 5), f64::from(u32::MAX - 5));
+        assert_eq!(converter.micros(10), (1u64 << 32) as f64 + 10.0);
+    }
+
+    #[test]
+    fn tasks_are_named_when_possible() {
+        let converter = convert(&[
+            "PETS-TRACE 0 0 100 rabbit",
+            "PETS-TRACE 1 0 200",
+            "PETS-TRACE 0 1 200 T001",
+            "PETS-TRACE 1 1 300",
+            "PETS-TRACE 2 3 300",
+        ]);
+        let output = converter.finish();
+        assert!(output.contains(r#""name":"rabbit","ph":"X","pid":1,"tid":0,"ts":100.000"#));
+        assert!(output.contains(r#""name":"T001","ph":"X","pid":1,"tid":1"#));
+        assert!(output.contains(r#""tid":3,"args":{"name":"T003"}"#));
+    }
+
+    #[test]
+    fn names_can_have_spaces() {
+        assert_eq!(
+            Record::parse("PETS-TRACE 0 4 100 uart rx  "),
+            Some(Record {
+                event: Event::SwitchIn,
+                task: Some(4),
+                timestamp: 100,
+                name: Some("uart rx".to_string()),
+            })
+        );
+        let output = convert(&["PETS-TRACE 0 4 100 uart rx"]).finish();
+        assert!(output.contains(r#""tid":4,"args":{"name":"uart rx"}"#));
+    }
+
+    #[test]
+    fn names_are_escaped() {
+        let output = convert(&[
+            r#"PETS-TRACE 0 0 100 say "hi""#,
+            "PETS-TRACE 1 0 200",
+            r"PETS-TRACE 0 1 200 back\slash",
+        ])
+        .finish();
+        assert!(output.contains(r#""name":"say \"hi\"","ph":"X""#));
+        assert!(output.contains(r#""tid":0,"args":{"name":"say \"hi\""}"#));
+        assert!(output.contains(r#""tid":1,"args":{"name":"back\\slash"}"#));
+        assert_eq!(json_escape("a\u{1}b\n"), r"a\u0001b\n");
+    }
+}
+
+// End of File